@@ -0,0 +1,363 @@
+//! adapting the concurrency of warming requests to how well the origin copes,
+//! by additive increase and multiplicative decrease.
+use crate::heater::ErrorKind;
+use reqwest::StatusCode;
+use std::{
+    pin::pin,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::sync::Notify;
+
+/// the fewest completed requests the concurrency is adapted after.
+const MIN_WINDOW: usize = 10;
+/// the error rate of a window above which the concurrency is halved.
+const BACKOFF_ERROR_RATE: f64 = 0.05;
+/// how much the p90 latency of a window may rise over the recent ones before
+/// the concurrency is halved.
+const LATENCY_FACTOR: f64 = 2.0;
+
+/// when the origin is considered overloaded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdaptivePolicy {
+    /// the error rate of a window, between 0 and 1, above which the circuit
+    /// breaker pauses all requests.
+    pub breaker_error_rate: f64,
+    pub breaker_pause: Duration,
+}
+
+impl Default for AdaptivePolicy {
+    fn default() -> Self {
+        AdaptivePolicy {
+            breaker_error_rate: 0.5,
+            breaker_pause: Duration::from_secs(30),
+        }
+    }
+}
+
+/// how a request went, for adapting the concurrency.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    /// a response, with the time until its headers
+    Response(Duration),
+    /// a 429 or 5xx response, a timeout or a refused connection
+    Overloaded,
+    /// failed for reasons unrelated to the load of the origin
+    Other,
+}
+
+impl Outcome {
+    pub fn of_response(status: StatusCode, elapsed: Duration) -> Self {
+        if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+            Outcome::Overloaded
+        } else {
+            Outcome::Response(elapsed)
+        }
+    }
+
+    pub fn of_error(kind: ErrorKind) -> Self {
+        match kind {
+            ErrorKind::Timeout | ErrorKind::Connect => Outcome::Overloaded,
+            _ => Outcome::Other,
+        }
+    }
+}
+
+/// how the concurrency was adapted, for the summary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Summary {
+    pub max: usize,
+    pub lowest: usize,
+    pub breaker_opened: usize,
+}
+
+#[derive(Debug)]
+struct State {
+    limit: usize,
+    in_flight: usize,
+    requests: usize,
+    overloaded: usize,
+    latencies: Vec<Duration>,
+    /// the moving average of the p90 latencies of the windows.
+    baseline: Option<Duration>,
+    paused_until: Option<Instant>,
+    lowest: usize,
+    breaker_opened: usize,
+}
+
+/// a concurrency limit between 1 and `max`, adapted after every window of
+/// completed requests.
+///
+/// Starting at `max`, the limit is halved when too many requests of a window
+/// were overloaded or its p90 latency rose, and raised by one otherwise. Above
+/// the error rate of the circuit breaker, all requests pause and continue one
+/// at a time.
+#[derive(Debug)]
+pub struct AdaptiveLimit {
+    policy: AdaptivePolicy,
+    max: usize,
+    state: Mutex<State>,
+    changed: Notify,
+}
+
+/// a slot of the limit, released when dropped.
+pub struct Permit<'a> {
+    limit: &'a AdaptiveLimit,
+}
+
+impl Permit<'_> {
+    /// release the slot and adapt the limit to the outcome.
+    pub fn finish(self, outcome: Outcome) {
+        self.limit.record(outcome, Instant::now());
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.limit.state.lock().unwrap().in_flight -= 1;
+        self.limit.changed.notify_waiters();
+    }
+}
+
+impl AdaptiveLimit {
+    pub fn new(policy: AdaptivePolicy, max: usize) -> Self {
+        let max = max.max(1);
+        AdaptiveLimit {
+            policy,
+            max,
+            state: Mutex::new(State {
+                limit: max,
+                in_flight: 0,
+                requests: 0,
+                overloaded: 0,
+                latencies: Vec::new(),
+                baseline: None,
+                paused_until: None,
+                lowest: max,
+                breaker_opened: 0,
+            }),
+            changed: Notify::new(),
+        }
+    }
+
+    /// wait for a slot, and for the circuit breaker to close.
+    pub async fn acquire(&self) -> Permit<'_> {
+        loop {
+            let mut changed = pin!(self.changed.notified());
+            changed.as_mut().enable();
+
+            let pause = {
+                let mut state = self.state.lock().unwrap();
+                match state.paused_until {
+                    Some(until) if until > Instant::now() => Some(until),
+                    _ if state.in_flight < state.limit => {
+                        state.paused_until = None;
+                        state.in_flight += 1;
+                        return Permit { limit: self };
+                    }
+                    _ => None,
+                }
+            };
+
+            match pause {
+                Some(until) => tokio::time::sleep_until(until.into()).await,
+                None => changed.await,
+            }
+        }
+    }
+
+    pub fn summary(&self) -> Summary {
+        let state = self.state.lock().unwrap();
+        Summary {
+            max: self.max,
+            lowest: state.lowest,
+            breaker_opened: state.breaker_opened,
+        }
+    }
+
+    fn record(&self, outcome: Outcome, now: Instant) {
+        let mut state = self.state.lock().unwrap();
+
+        state.requests += 1;
+        match outcome {
+            Outcome::Response(latency) => state.latencies.push(latency),
+            Outcome::Overloaded => state.overloaded += 1,
+            Outcome::Other => {}
+        }
+        if state.requests < state.limit.max(MIN_WINDOW) {
+            return;
+        }
+
+        let error_rate = state.overloaded as f64 / state.requests as f64;
+        let p90 = percentile(&mut state.latencies, 0.9);
+        let slow = match (p90, state.baseline) {
+            (Some(p90), Some(baseline)) => {
+                p90.as_secs_f64() > baseline.as_secs_f64() * LATENCY_FACTOR
+            }
+            _ => false,
+        };
+
+        if error_rate >= self.policy.breaker_error_rate {
+            state.limit = 1;
+            state.paused_until = Some(now + self.policy.breaker_pause);
+            state.breaker_opened += 1;
+            log::warn!(
+                "{:.0}% of the requests were overloaded, pausing for {:?}",
+                error_rate * 100.0,
+                self.policy.breaker_pause
+            );
+        } else if error_rate > BACKOFF_ERROR_RATE || slow {
+            state.limit = (state.limit / 2).max(1);
+            log::info!(
+                "origin is struggling, reducing concurrency to {}",
+                state.limit
+            );
+        } else if state.limit < self.max {
+            state.limit += 1;
+            log::debug!("increasing concurrency to {}", state.limit);
+        }
+
+        // a lasting change of the latency becomes the new normal
+        if let Some(p90) = p90 {
+            state.baseline = Some(match state.baseline {
+                Some(baseline) => baseline.mul_f64(0.8) + p90.mul_f64(0.2),
+                None => p90,
+            });
+        }
+
+        state.lowest = state.lowest.min(state.limit);
+        state.requests = 0;
+        state.overloaded = 0;
+        state.latencies.clear();
+        drop(state);
+
+        self.changed.notify_waiters();
+    }
+}
+
+fn percentile(latencies: &mut [Duration], quantile: f64) -> Option<Duration> {
+    if latencies.is_empty() {
+        return None;
+    }
+    latencies.sort_unstable();
+    let index = ((latencies.len() as f64 * quantile).ceil() as usize).clamp(1, latencies.len());
+    Some(latencies[index - 1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record_window(limit: &AdaptiveLimit, overloaded: usize, latency: Duration) {
+        let now = Instant::now();
+        let requests = limit.state.lock().unwrap().limit.max(MIN_WINDOW);
+        for i in 0..requests {
+            let outcome = if i < overloaded {
+                Outcome::Overloaded
+            } else {
+                Outcome::Response(latency)
+            };
+            limit.record(outcome, now);
+        }
+    }
+
+    fn current(limit: &AdaptiveLimit) -> usize {
+        limit.state.lock().unwrap().limit
+    }
+
+    #[test]
+    fn additive_increase_multiplicative_decrease() {
+        let limit = AdaptiveLimit::new(AdaptivePolicy::default(), 16);
+        let ms = Duration::from_millis;
+
+        record_window(&limit, 0, ms(100));
+        assert_eq!(current(&limit), 16);
+
+        // 3 of 16 overloaded
+        record_window(&limit, 3, ms(100));
+        assert_eq!(current(&limit), 8);
+        // recovering
+        record_window(&limit, 0, ms(100));
+        assert_eq!(current(&limit), 9);
+
+        // the latency doubled
+        record_window(&limit, 0, ms(250));
+        assert_eq!(current(&limit), 4);
+        // and becomes the new normal
+        record_window(&limit, 0, ms(250));
+        record_window(&limit, 0, ms(250));
+        assert_eq!(current(&limit), 6);
+
+        assert_eq!(
+            limit.summary(),
+            Summary {
+                max: 16,
+                lowest: 4,
+                breaker_opened: 0
+            }
+        );
+    }
+
+    #[test]
+    fn circuit_breaker() {
+        let policy = AdaptivePolicy {
+            breaker_error_rate: 0.5,
+            breaker_pause: Duration::from_secs(60),
+        };
+        let limit = AdaptiveLimit::new(policy, 8);
+
+        record_window(&limit, 5, Duration::from_millis(100));
+
+        let state = limit.state.lock().unwrap();
+        assert_eq!(state.limit, 1);
+        assert!(state.paused_until.unwrap() > Instant::now() + Duration::from_secs(59));
+        assert_eq!(state.breaker_opened, 1);
+    }
+
+    #[tokio::test]
+    async fn acquire() {
+        let policy = AdaptivePolicy {
+            breaker_error_rate: 0.5,
+            breaker_pause: Duration::from_millis(100),
+        };
+        let limit = AdaptiveLimit::new(policy, 2);
+
+        let first = limit.acquire().await;
+        let second = limit.acquire().await;
+        // the limit is reached
+        assert!(
+            tokio::time::timeout(Duration::from_millis(20), limit.acquire())
+                .await
+                .is_err()
+        );
+        first.finish(Outcome::Other);
+        drop(limit.acquire().await);
+        second.finish(Outcome::Other);
+
+        // opens the circuit breaker
+        record_window(&limit, 10, Duration::ZERO);
+        let start = Instant::now();
+        let permit = limit.acquire().await;
+        assert!(start.elapsed() >= Duration::from_millis(90));
+        assert!(
+            tokio::time::timeout(Duration::from_millis(20), limit.acquire())
+                .await
+                .is_err()
+        );
+        drop(permit);
+    }
+
+    #[test]
+    fn percentiles() {
+        let mut latencies: Vec<Duration> = (1..=10).rev().map(Duration::from_secs).collect();
+        assert_eq!(
+            percentile(&mut latencies, 0.9),
+            Some(Duration::from_secs(9))
+        );
+        assert_eq!(
+            percentile(&mut latencies, 0.5),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(percentile(&mut [], 0.9), None);
+    }
+}
@@ -0,0 +1,171 @@
+//! finding the subresources of pages, like stylesheets, scripts and images.
+use crate::html;
+use reqwest::header::{self, HeaderMap};
+use url::Url;
+
+/// `rel` values of `<link>` elements that reference subresources.
+const LINK_RELS: [&str; 3] = ["stylesheet", "preload", "modulepreload"];
+
+/// the subresources referenced in the HTML page: `<link rel=stylesheet|preload>`,
+/// `<script src>` and `<img src/srcset>`.
+pub(crate) fn from_html(url: &Url, body: &str) -> Vec<Url> {
+    let tags = html::tags(body);
+    let base = html::base(url, &tags);
+
+    let mut result: Vec<Url> = Vec::new();
+    let mut push = |href: &str| {
+        if let Some(url) = html::resolve(&base, href) {
+            result.push(url);
+        }
+    };
+
+    for tag in &tags {
+        match tag.name.as_str() {
+            "link" => {
+                let is_asset = tag.attribute("rel").is_some_and(|rel| {
+                    rel.split_ascii_whitespace()
+                        .any(|r| LINK_RELS.iter().any(|l| r.eq_ignore_ascii_case(l)))
+                });
+                if is_asset && let Some(href) = tag.attribute("href") {
+                    push(href);
+                }
+            }
+            "script" => {
+                if let Some(src) = tag.attribute("src") {
+                    push(src);
+                }
+            }
+            "img" => {
+                if let Some(src) = tag.attribute("src") {
+                    push(src);
+                }
+                if let Some(srcset) = tag.attribute("srcset") {
+                    srcset_urls(srcset).for_each(&mut push);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    result
+}
+
+/// the preloaded subresources from `Link: <url>; rel=preload` headers.
+pub(crate) fn from_link_headers(url: &Url, headers: &HeaderMap) -> Vec<Url> {
+    headers
+        .get_all(header::LINK)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(parse_link_header)
+        .filter(|(_, rels)| {
+            rels.split_ascii_whitespace().any(|r| {
+                r.eq_ignore_ascii_case("preload") || r.eq_ignore_ascii_case("modulepreload")
+            })
+        })
+        .filter_map(|(href, _)| html::resolve(url, href))
+        .collect()
+}
+
+/// the URLs of the candidates in a `srcset`, like `a.jpg 1x, b.jpg 2x`.
+fn srcset_urls(srcset: &str) -> impl Iterator<Item = &str> {
+    srcset
+        .split(',')
+        .filter_map(|candidate| candidate.split_ascii_whitespace().next())
+}
+
+/// split a `Link` header into its link targets and their `rel` parameter.
+fn parse_link_header(value: &str) -> Vec<(&str, &str)> {
+    let mut result: Vec<(&str, &str)> = Vec::new();
+    let mut rest = value;
+
+    while let Some(start) = rest.find('<') {
+        let Some(end) = rest[start..].find('>').map(|end| start + end) else {
+            break;
+        };
+        let href = &rest[start + 1..end];
+
+        // the parameters end at the next link
+        rest = &rest[end + 1..];
+        let params_end = rest.find(',').unwrap_or(rest.len());
+        let rel = rest[..params_end]
+            .split(';')
+            .filter_map(|param| param.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("rel"))
+            .map(|(_, value)| value.trim().trim_matches('"'))
+            .unwrap_or("");
+
+        result.push((href, rel));
+        rest = &rest[params_end..];
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn url(path: &str) -> Url {
+        Url::parse("https://www.example.com/")
+            .unwrap()
+            .join(path)
+            .unwrap()
+    }
+
+    #[test]
+    fn html_assets() {
+        let body = r#"<html><head>
+            <link rel="stylesheet" href="/style.css">
+            <link rel="preload" href="/font.woff2" as="font">
+            <link rel="canonical" href="/canonical">
+            <link rel="icon" href="/favicon.ico">
+            <script src="/app.js"></script>
+            <script>var inline = '<img src="/inline.png">';</script>
+            </head><body>
+            <a href="/page">page</a>
+            <img src="image.jpg" srcset="image-2x.jpg 2x, https://cdn.example.com/image-3x.jpg 3x">
+            <img alt="without src">
+            </body></html>"#;
+
+        assert_eq!(
+            from_html(&url("/dir/"), body)[..],
+            [
+                url("/style.css"),
+                url("/font.woff2"),
+                url("/app.js"),
+                url("/dir/image.jpg"),
+                url("/dir/image-2x.jpg"),
+                Url::parse("https://cdn.example.com/image-3x.jpg").unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn link_headers() {
+        let mut headers = HeaderMap::new();
+        headers.append(
+            header::LINK,
+            HeaderValue::from_static(
+                r#"</style.css>; rel=preload; as=style, <https://cdn.example.com/app.js>; rel="modulepreload""#,
+            ),
+        );
+        headers.append(
+            header::LINK,
+            HeaderValue::from_static(r#"<https://www.example.com/>; rel="preconnect""#),
+        );
+        headers.append(
+            header::LINK,
+            HeaderValue::from_static(r#"<font.woff2>; as=font; rel=preload; crossorigin"#),
+        );
+
+        assert_eq!(
+            from_link_headers(&url("/dir/page"), &headers)[..],
+            [
+                url("/style.css"),
+                Url::parse("https://cdn.example.com/app.js").unwrap(),
+                url("/dir/font.woff2"),
+            ]
+        );
+    }
+}
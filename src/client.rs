@@ -0,0 +1,196 @@
+//! the HTTP clients, configured from the `Config`.
+use crate::config::Config;
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    header::HeaderMap,
+    Client, ClientBuilder,
+};
+use std::{io, net::ToSocketAddrs};
+use thiserror::Error;
+
+/// resolving a host name failed.
+#[derive(Error, Debug)]
+#[error("could not resolve {host}: {source}")]
+pub struct DnsError {
+    host: String,
+    #[source]
+    source: io::Error,
+}
+
+/// resolves host names like the system does, but with errors that can be
+/// told apart from other connect errors.
+struct Resolver;
+
+impl Resolve for Resolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_owned();
+        Box::pin(async move {
+            let resolved = tokio::task::spawn_blocking({
+                let host = host.clone();
+                move || resolve(&host)
+            })
+            .await
+            .unwrap_or_else(|err| Err(io::Error::other(err)));
+
+            match resolved {
+                Ok(addrs) => Ok(Box::new(addrs.into_iter()) as Addrs),
+                Err(source) => Err(Box::new(DnsError { host, source }) as _),
+            }
+        })
+    }
+}
+
+fn resolve(host: &str) -> io::Result<Vec<std::net::SocketAddr>> {
+    // reserved to never resolve (RFC 6761), without asking the system
+    let tld = host.trim_end_matches('.').rsplit('.').next();
+    if tld.is_some_and(|tld| tld.eq_ignore_ascii_case("invalid")) {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "the invalid top-level domain never resolves",
+        ));
+    }
+    Ok((host, 0).to_socket_addrs()?.collect())
+}
+
+/// the client for warming and crawling pages.
+pub fn build(config: &Config) -> Client {
+    builder(config, config.request_headers.clone())
+        .build()
+        .expect("could not initialize HTTP client")
+}
+
+/// the client for sitemaps and robots.txt.
+pub fn build_for_sitemaps(config: &Config) -> Client {
+    builder(config, sitemap_headers(config))
+        .build()
+        .expect("could not initialize HTTP client")
+}
+
+/// the headers of sitemaps and robots.txt, where the sitemap headers replace
+/// the request headers of the same name.
+pub fn sitemap_headers(config: &Config) -> HeaderMap {
+    let mut headers = config.request_headers.clone();
+    for name in config.sitemap_headers.keys() {
+        headers.remove(name);
+        for value in config.sitemap_headers.get_all(name) {
+            headers.append(name, value.clone());
+        }
+    }
+    headers
+}
+
+fn builder(config: &Config, headers: HeaderMap) -> ClientBuilder {
+    // the default headers replace the user agent
+    let mut builder = Client::builder()
+        .gzip(true)
+        .user_agent(config.user_agent.clone())
+        .default_headers(headers)
+        .dns_resolver(Resolver)
+        .danger_accept_invalid_certs(config.accept_invalid_certs);
+
+    for certificate in &config.root_certificates {
+        builder = builder.add_root_certificate(certificate.clone());
+    }
+    if let Some(ref proxy) = config.proxy {
+        builder = builder.proxy(proxy.clone());
+    }
+    if let Some(timeout) = config.connect_timeout {
+        builder = builder.connect_timeout(timeout);
+    }
+    if let Some(timeout) = config.timeout {
+        builder = builder.timeout(timeout);
+    }
+
+    builder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{self, APP_USER_AGENT};
+    use reqwest::header::{self, HeaderValue};
+
+    fn config() -> Config {
+        let mut config = Config::new();
+        config.request_headers.insert(
+            header::AUTHORIZATION,
+            config::parse_basic_auth("user:secret").unwrap(),
+        );
+        config
+            .request_headers
+            .insert("x-token", HeaderValue::from_static("pages"));
+        config
+    }
+
+    #[tokio::test]
+    async fn request_headers() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/")
+            .match_header(header::USER_AGENT.as_str(), APP_USER_AGENT)
+            .match_header(header::AUTHORIZATION.as_str(), "Basic dXNlcjpzZWNyZXQ=")
+            .match_header("x-token", "pages")
+            .create_async()
+            .await;
+
+        build(&config()).get(server.url()).send().await.unwrap();
+
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn sitemap_headers() {
+        let mut config = config();
+        config.user_agent = HeaderValue::from_static("custom");
+        config
+            .sitemap_headers
+            .append("x-token", HeaderValue::from_static("sitemaps"));
+
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/sitemap.xml")
+            .match_header(header::USER_AGENT.as_str(), "custom")
+            .match_header(header::AUTHORIZATION.as_str(), "Basic dXNlcjpzZWNyZXQ=")
+            .match_header("x-token", "sitemaps")
+            .create_async()
+            .await;
+
+        build_for_sitemaps(&config)
+            .get(format!("{}/sitemap.xml", server.url()))
+            .send()
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn dns_error() {
+        let err = build(&Config::new())
+            .get("http://heater-test.invalid/")
+            .send()
+            .await
+            .unwrap_err();
+
+        let mut source = std::error::Error::source(&err);
+        while let Some(err) = source
+            && !err.is::<DnsError>()
+        {
+            source = err.source();
+        }
+        assert!(source.is_some());
+    }
+
+    #[tokio::test]
+    async fn timeout() {
+        let mut config = Config::new();
+        config.timeout = Some(std::time::Duration::from_millis(100));
+
+        // nothing answers on the listener
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+
+        let err = build(&config).get(url).send().await.unwrap_err();
+        assert!(err.is_timeout());
+    }
+}
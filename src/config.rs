@@ -1,10 +1,31 @@
+use crate::{
+    adaptive::AdaptivePolicy,
+    cache::SitemapCache,
+    filters::UrlPattern,
+    heater::ErrorKind,
+    rate::{HostRate, Rate, RateLimiter},
+    retry::RetryPolicy,
+    rewrites::{HostRewrite, PrefixRewrite},
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use clap::ArgMatches;
 use itertools::Itertools;
-use reqwest::header::{self, HeaderMap, HeaderName, HeaderValue};
+use reqwest::{
+    header::{self, HeaderMap, HeaderName, HeaderValue},
+    Certificate, Proxy, StatusCode,
+};
 use std::collections::HashSet;
 use std::convert::TryInto;
 use std::iter;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use thiserror::Error;
+use tokio::sync::Semaphore;
+
+/// the sitemap protocol allows up to 50MB (uncompressed) per sitemap.
+pub const MAX_SITEMAP_SIZE: usize = 50 * 1024 * 1024;
 
 pub const APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION"),);
 
@@ -30,12 +51,125 @@ pub enum Error {
 
     #[error("invalid header value")]
     InvalidHeaderValue(#[from] header::InvalidHeaderValue),
+
+    #[error("invalid order: {0}")]
+    InvalidOrder(String),
+
+    #[error("invalid percentage: {0}")]
+    InvalidPercentage(String),
+
+    #[error("missing password, expected USER:PASSWORD")]
+    MissingPassword,
+
+    #[error("could not read certificate {0}")]
+    CertificateFile(String, #[source] std::io::Error),
+
+    #[error("invalid certificate {0}")]
+    InvalidCertificate(String, #[source] reqwest::Error),
+}
+
+/// the order in which the sitemap entries are warmed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Order {
+    /// keep the order of the sitemap
+    #[default]
+    Sitemap,
+    /// highest priority first
+    Priority,
+    /// most recently modified first
+    LastMod,
+    /// most frequently changing first
+    ChangeFreq,
+}
+
+impl Order {
+    pub const NAMES: [&'static str; 4] = ["sitemap", "priority", "lastmod", "changefreq"];
 }
 
-#[derive(Debug)]
+impl FromStr for Order {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sitemap" => Ok(Order::Sitemap),
+            "priority" => Ok(Order::Priority),
+            "lastmod" => Ok(Order::LastMod),
+            "changefreq" => Ok(Order::ChangeFreq),
+            _ => Err(Error::InvalidOrder(s.to_owned())),
+        }
+    }
+}
+
+/// how many of the sitemap URLs are warmed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sample {
+    /// a fixed number of URLs
+    Count(usize),
+    /// a percentage of the URLs, between 0 and 100
+    Percent(f64),
+}
+
+#[derive(Debug, Clone)]
 pub struct Config {
     pub concurrent_requests: usize,
+    pub user_agent: HeaderValue,
+    /// sent with every request, including sitemaps.
+    pub request_headers: HeaderMap,
+    /// replace the request headers of the same name when fetching sitemaps
+    /// and robots.txt.
+    pub sitemap_headers: HeaderMap,
+    pub proxy: Option<Proxy>,
+    /// trusted in addition to the system certificates.
+    pub root_certificates: Vec<Certificate>,
+    pub accept_invalid_certs: bool,
+    pub connect_timeout: Option<Duration>,
+    pub timeout: Option<Duration>,
+    pub retry: RetryPolicy,
+    /// limits the requests of all sites warmed in one run, on top of
+    /// `concurrent_requests`.
+    pub request_limit: Option<Arc<Semaphore>>,
+    /// limits the requests per second of all sites warmed in one run.
+    pub rate_limit: Option<Arc<RateLimiter>>,
+    /// adapt the concurrency up to `concurrent_requests` to how the origin
+    /// copes, fixed without a policy.
+    pub adaptive: Option<AdaptivePolicy>,
+    /// no warming requests are started after it.
+    pub deadline: Option<Instant>,
+    pub robots: bool,
+    pub crawl: bool,
+    pub concurrent_sitemap_requests: usize,
+    pub max_sitemap_depth: usize,
+    pub max_sitemap_urls: usize,
+    pub max_sitemap_size: usize,
+    pub sitemap_retries: usize,
+    pub skip_broken_sitemaps: bool,
+    /// the directory of the sitemap cache, no caching without one.
+    pub sitemap_cache: Option<PathBuf>,
+    /// cache sitemaps requested with credentials too.
+    pub cache_authenticated_sitemaps: bool,
+    pub crawl_max_depth: usize,
+    pub crawl_max_pages: usize,
+    pub crawl_hosts: Vec<String>,
+    pub crawl_prefixes: Vec<String>,
+    pub include: Vec<UrlPattern>,
+    pub exclude: Vec<UrlPattern>,
+    pub rewrite_prefixes: Vec<PrefixRewrite>,
+    pub rewrite_scheme: Option<String>,
+    pub rewrite_host: Option<HostRewrite>,
+    pub keep_host_header: bool,
+    pub modified_since: Option<Duration>,
+    pub min_priority: Option<f32>,
+    pub order: Order,
+    pub sample: Option<Sample>,
+    pub sample_seed: Option<u64>,
+    pub sample_stratified: bool,
+    pub hreflang: bool,
+    pub hreflang_languages: bool,
+    pub include_media: bool,
+    pub include_assets: bool,
     header_variations: HeaderMap,
+    media_header_variations: HeaderMap,
+    asset_header_variations: HeaderMap,
     languages: HashSet<HeaderValue>,
 }
 
@@ -43,7 +177,52 @@ impl Config {
     pub fn new() -> Self {
         Config {
             concurrent_requests: num_cpus::get(),
+            user_agent: HeaderValue::from_static(APP_USER_AGENT),
+            request_headers: HeaderMap::new(),
+            sitemap_headers: HeaderMap::new(),
+            proxy: None,
+            root_certificates: Vec::new(),
+            accept_invalid_certs: false,
+            connect_timeout: None,
+            timeout: None,
+            retry: RetryPolicy::default(),
+            request_limit: None,
+            rate_limit: None,
+            adaptive: None,
+            deadline: None,
+            robots: false,
+            crawl: false,
+            concurrent_sitemap_requests: num_cpus::get(),
+            max_sitemap_depth: 5,
+            max_sitemap_urls: 10_000_000,
+            max_sitemap_size: MAX_SITEMAP_SIZE,
+            sitemap_retries: 2,
+            skip_broken_sitemaps: false,
+            sitemap_cache: None,
+            cache_authenticated_sitemaps: false,
+            crawl_max_depth: 3,
+            crawl_max_pages: 1000,
+            crawl_hosts: Vec::new(),
+            crawl_prefixes: Vec::new(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            rewrite_prefixes: Vec::new(),
+            rewrite_scheme: None,
+            rewrite_host: None,
+            keep_host_header: false,
+            modified_since: None,
+            min_priority: None,
+            order: Order::default(),
+            sample: None,
+            sample_seed: None,
+            sample_stratified: false,
+            hreflang: false,
+            hreflang_languages: false,
+            include_media: false,
+            include_assets: false,
             header_variations: HeaderMap::new(),
+            media_header_variations: HeaderMap::new(),
+            asset_header_variations: HeaderMap::new(),
             languages: HashSet::new(),
         }
     }
@@ -59,6 +238,74 @@ impl Config {
         self.header_variations.append(header, value);
     }
 
+    pub fn add_media_header_variation<TH, TV>(&mut self, header: TH, value: TV)
+    where
+        TH: TryInto<HeaderName>,
+        TH::Error: std::fmt::Debug,
+        TV: TryInto<HeaderValue>,
+        TV::Error: std::fmt::Debug,
+    {
+        let (header, value) = parse_header_tuple!(header, value);
+        self.media_header_variations.append(header, value);
+    }
+
+    /// the config used to warm images & videos.
+    ///
+    /// Media gets its own header variations, and no language variations.
+    pub fn for_media(&self) -> Self {
+        Config {
+            header_variations: self.media_header_variations.clone(),
+            languages: HashSet::new(),
+            ..self.clone()
+        }
+    }
+
+    pub fn add_asset_header_variation<TH, TV>(&mut self, header: TH, value: TV)
+    where
+        TH: TryInto<HeaderName>,
+        TH::Error: std::fmt::Debug,
+        TV: TryInto<HeaderValue>,
+        TV::Error: std::fmt::Debug,
+    {
+        let (header, value) = parse_header_tuple!(header, value);
+        self.asset_header_variations.append(header, value);
+    }
+
+    /// the config used to warm the subresources of pages.
+    ///
+    /// Like media, assets get their own header variations, and no language
+    /// variations. Their own subresources are not looked for.
+    pub fn for_assets(&self) -> Self {
+        Config {
+            header_variations: self.asset_header_variations.clone(),
+            languages: HashSet::new(),
+            include_assets: false,
+            ..self.clone()
+        }
+    }
+
+    /// the config of a site with its own header variations and languages,
+    /// the configured ones are kept when none are given.
+    pub(crate) fn for_site(&self, headers: &[HeaderVariation], languages: &[String]) -> Self {
+        let mut config = self.clone();
+
+        if !headers.is_empty() {
+            config.header_variations = HeaderMap::new();
+            for hv in headers {
+                config.add_header_variation(&hv.header, &hv.value);
+            }
+        }
+
+        if !languages.is_empty() {
+            config.languages = HashSet::new();
+            for language in languages {
+                config.add_language_variation(language);
+            }
+        }
+
+        config
+    }
+
     pub fn add_language_variation<T>(&mut self, language: T)
     where
         T: TryInto<HeaderValue>,
@@ -77,6 +324,177 @@ impl Config {
             }
         }
 
+        if let Some(value) = arguments.get_one::<HeaderValue>("user_agent") {
+            config.user_agent = value.clone();
+        }
+
+        if let Some(values) = arguments.get_many::<HeaderVariation>("request_header") {
+            for hv in values {
+                config.request_headers.append(&hv.header, hv.value.clone());
+            }
+        }
+
+        if let Some(value) = arguments.get_one::<HeaderValue>("auth") {
+            config
+                .request_headers
+                .insert(header::AUTHORIZATION, value.clone());
+        }
+
+        if let Some(values) = arguments.get_many::<HeaderVariation>("sitemap_header") {
+            for hv in values {
+                config.sitemap_headers.append(&hv.header, hv.value.clone());
+            }
+        }
+
+        config.proxy = arguments.get_one::<Proxy>("proxy").cloned();
+
+        if let Some(values) = arguments.get_many::<Certificate>("ca_cert") {
+            config.root_certificates = values.cloned().collect();
+        }
+
+        config.accept_invalid_certs = arguments.get_flag("insecure");
+        config.connect_timeout = arguments.get_one::<Duration>("connect_timeout").copied();
+        config.timeout = arguments.get_one::<Duration>("timeout").copied();
+        // the time budget of the run starts now, before the discovery
+        config.deadline = arguments
+            .get_one::<Duration>("deadline")
+            .map(|duration| Instant::now() + *duration);
+
+        if let Some(value) = arguments.get_one::<usize>("retries") {
+            config.retry.retries = *value;
+        }
+        if let Some(values) = arguments.get_many::<StatusCode>("retry_status") {
+            config.retry.statuses = values.copied().collect();
+        }
+        if let Some(values) = arguments.get_many::<ErrorKind>("retry_error") {
+            config.retry.errors = values.copied().collect();
+        }
+        if let Some(value) = arguments.get_one::<Duration>("retry_delay") {
+            config.retry.delay = *value;
+        }
+        if let Some(value) = arguments.get_one::<Duration>("retry_max_delay") {
+            config.retry.max_delay = *value;
+        }
+
+        let host_rates: Vec<HostRate> = arguments
+            .get_many::<HostRate>("host_rate")
+            .map(|values| values.cloned().collect())
+            .unwrap_or_default();
+        config.rate_limit =
+            RateLimiter::new(arguments.get_one::<Rate>("rate").copied(), &host_rates).map(Arc::new);
+
+        if arguments.get_flag("adaptive") {
+            let mut policy = AdaptivePolicy::default();
+            if let Some(value) = arguments.get_one::<f64>("breaker_error_rate") {
+                policy.breaker_error_rate = value / 100.0;
+            }
+            if let Some(value) = arguments.get_one::<Duration>("breaker_pause") {
+                policy.breaker_pause = *value;
+            }
+            config.adaptive = Some(policy);
+        }
+
+        config.robots = arguments.get_flag("robots");
+        config.crawl = arguments.get_flag("crawl");
+
+        if let Some(value) = arguments.get_one::<usize>("sitemap_concurrency") {
+            config.concurrent_sitemap_requests = *value;
+        }
+
+        if let Some(value) = arguments.get_one::<usize>("max_sitemap_depth") {
+            config.max_sitemap_depth = *value;
+        }
+
+        if let Some(value) = arguments.get_one::<usize>("max_sitemap_urls") {
+            config.max_sitemap_urls = *value;
+        }
+
+        if let Some(value) = arguments.get_one::<usize>("max_sitemap_size") {
+            config.max_sitemap_size = *value;
+        }
+
+        if let Some(value) = arguments.get_one::<usize>("sitemap_retries") {
+            config.sitemap_retries = *value;
+        }
+
+        config.skip_broken_sitemaps = arguments.get_flag("skip_broken_sitemaps");
+
+        if !arguments.get_flag("no_sitemap_cache") {
+            config.sitemap_cache = arguments
+                .get_one::<PathBuf>("sitemap_cache")
+                .cloned()
+                .or_else(SitemapCache::default_dir);
+            config.cache_authenticated_sitemaps =
+                arguments.get_flag("cache_authenticated_sitemaps");
+        }
+
+        if let Some(value) = arguments.get_one::<usize>("crawl_depth") {
+            config.crawl_max_depth = *value;
+        }
+
+        if let Some(value) = arguments.get_one::<usize>("crawl_limit") {
+            config.crawl_max_pages = *value;
+        }
+
+        if let Some(values) = arguments.get_many::<String>("crawl_host") {
+            config.crawl_hosts = values.cloned().collect();
+        }
+
+        if let Some(values) = arguments.get_many::<String>("crawl_prefix") {
+            config.crawl_prefixes = values.cloned().collect();
+        }
+
+        if let Some(values) = arguments.get_many::<UrlPattern>("include") {
+            config.include = values.cloned().collect();
+        }
+
+        if let Some(values) = arguments.get_many::<UrlPattern>("exclude") {
+            config.exclude = values.cloned().collect();
+        }
+
+        if let Some(values) = arguments.get_many::<PrefixRewrite>("rewrite") {
+            config.rewrite_prefixes = values.cloned().collect();
+        }
+
+        config.rewrite_scheme = arguments.get_one::<String>("rewrite_scheme").cloned();
+        config.rewrite_host = arguments.get_one::<HostRewrite>("rewrite_host").cloned();
+        config.keep_host_header = arguments.get_flag("keep_host_header");
+
+        config.modified_since = arguments.get_one::<Duration>("modified_since").copied();
+        config.min_priority = arguments.get_one::<f32>("min_priority").copied();
+
+        if let Some(value) = arguments.get_one::<Order>("order") {
+            config.order = *value;
+        }
+
+        if let Some(value) = arguments.get_one::<usize>("sample") {
+            config.sample = Some(Sample::Count(*value));
+        }
+        if let Some(value) = arguments.get_one::<f64>("sample_percent") {
+            config.sample = Some(Sample::Percent(*value));
+        }
+        config.sample_seed = arguments.get_one::<u64>("seed").copied();
+        config.sample_stratified = arguments.get_flag("stratified");
+
+        config.hreflang_languages = arguments.get_flag("hreflang_languages");
+        config.hreflang = arguments.get_flag("hreflang") || config.hreflang_languages;
+
+        config.include_media = arguments.get_flag("include_media");
+
+        if let Some(values) = arguments.get_many::<HeaderVariation>("media_header_variation") {
+            for hv in values {
+                config.add_media_header_variation(&hv.header, &hv.value);
+            }
+        }
+
+        config.include_assets = arguments.get_flag("include_assets");
+
+        if let Some(values) = arguments.get_many::<HeaderVariation>("asset_header_variation") {
+            for hv in values {
+                config.add_asset_header_variation(&hv.header, &hv.value);
+            }
+        }
+
         if let Some(values) = arguments.get_many::<String>("language") {
             for value in values {
                 config.add_language_variation(value);
@@ -91,6 +509,11 @@ impl Config {
         self.generate_header_variations().count() as u64
     }
 
+    /// like `possible_variations`, but for a custom set of languages.
+    pub fn possible_variations_for(&self, languages: Option<&[HeaderValue]>) -> u64 {
+        self.generate_header_variations_for(languages).count() as u64
+    }
+
     fn generate_language_variations(&self) -> Vec<HeaderValue> {
         let (empty, languages): (Vec<String>, Vec<String>) = self
             .languages
@@ -138,14 +561,24 @@ impl Config {
     }
 
     pub fn generate_header_variations(&self) -> impl Iterator<Item = HeaderMap> + Clone {
+        self.generate_header_variations_for(None)
+    }
+
+    /// generate the header variations, using the given accept-language
+    /// values instead of the configured language permutations.
+    pub fn generate_header_variations_for(
+        &self,
+        languages: Option<&[HeaderValue]>,
+    ) -> impl Iterator<Item = HeaderMap> + Clone + use<> {
         let mut header_variations = self.header_variations.clone();
 
-        header_variations.insert(header::USER_AGENT, HeaderValue::from_static(APP_USER_AGENT));
-        header_variations.extend(
-            self.generate_language_variations()
-                .into_iter()
-                .map(|v| (header::ACCEPT_LANGUAGE, v)),
-        );
+        let languages = match languages {
+            Some(languages) => languages.to_vec(),
+            None => self.generate_language_variations(),
+        };
+
+        header_variations.insert(header::USER_AGENT, self.user_agent.clone());
+        header_variations.extend(languages.into_iter().map(|v| (header::ACCEPT_LANGUAGE, v)));
 
         // for every header-name, create a list of pairs (headername, value)
         // with all possible values for that header
@@ -192,6 +625,30 @@ pub(crate) fn parse_header(input: &str) -> Result<HeaderVariation, Error> {
     Ok(HeaderVariation { header, value })
 }
 
+/// parse `USER:PASSWORD` into a basic `Authorization` header value.
+pub(crate) fn parse_basic_auth(input: &str) -> Result<HeaderValue, Error> {
+    if !input.contains(':') {
+        return Err(Error::MissingPassword);
+    }
+
+    let mut value = HeaderValue::try_from(format!("Basic {}", BASE64.encode(input)))?;
+    value.set_sensitive(true);
+    Ok(value)
+}
+
+/// read a PEM encoded certificate from the file.
+pub(crate) fn read_certificate(path: &str) -> Result<Certificate, Error> {
+    let pem = std::fs::read(path).map_err(|err| Error::CertificateFile(path.to_owned(), err))?;
+    Certificate::from_pem(&pem).map_err(|err| Error::InvalidCertificate(path.to_owned(), err))
+}
+
+pub(crate) fn parse_percentage(input: &str) -> Result<f64, Error> {
+    match input.trim_end_matches('%').parse::<f64>() {
+        Ok(value) if value > 0.0 && value <= 100.0 => Ok(value),
+        _ => Err(Error::InvalidPercentage(input.to_owned())),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -204,6 +661,31 @@ mod tests {
         assert_eq!(APP_USER_AGENT.chars().filter(|&c| c == '.').count(), 2);
     }
 
+    #[test_case("user:secret", "Basic dXNlcjpzZWNyZXQ="; "simple")]
+    #[test_case("user:", "Basic dXNlcjo="; "empty password")]
+    #[test_case("user:p:w", "Basic dXNlcjpwOnc="; "colon in password")]
+    fn basic_auth_ok(input: &str, expected: &str) {
+        let value = parse_basic_auth(input).unwrap();
+        assert_eq!(value, expected);
+        assert!(value.is_sensitive());
+    }
+
+    #[test]
+    fn basic_auth_err() {
+        assert!(matches!(
+            parse_basic_auth("user"),
+            Err(Error::MissingPassword)
+        ));
+    }
+
+    #[test]
+    fn certificate_err() {
+        assert!(matches!(
+            read_certificate("/nonexistent/ca.pem"),
+            Err(Error::CertificateFile(..))
+        ));
+    }
+
     #[test_case(""; "empty")]
     #[test_case(":"; "only colon")]
     #[test_case("a test with space:value "; "spaces")]
@@ -233,6 +715,37 @@ mod tests {
         );
     }
 
+    #[test_case("sitemap", Order::Sitemap)]
+    #[test_case("priority", Order::Priority)]
+    #[test_case("lastmod", Order::LastMod)]
+    #[test_case("changefreq", Order::ChangeFreq)]
+    fn order_from_str(text: &str, expected: Order) {
+        assert_eq!(text.parse::<Order>().unwrap(), expected);
+    }
+
+    #[test_case("10", 10.0)]
+    #[test_case("2.5%", 2.5)]
+    #[test_case("100", 100.0)]
+    fn percentage_ok(text: &str, expected: f64) {
+        assert_eq!(parse_percentage(text).unwrap(), expected);
+    }
+
+    #[test_case("0")]
+    #[test_case("101")]
+    #[test_case("-1")]
+    #[test_case("some")]
+    fn percentage_err(text: &str) {
+        assert!(parse_percentage(text).is_err());
+    }
+
+    #[test]
+    fn order_names() {
+        for name in Order::NAMES {
+            assert!(name.parse::<Order>().is_ok());
+        }
+        assert!("random".parse::<Order>().is_err());
+    }
+
     #[test]
     fn variations_empty() {
         let cfg = Config::new();
@@ -312,6 +825,110 @@ mod tests {
         assert_eq!(header_values, expected);
     }
 
+    #[test]
+    fn variations_custom_languages() {
+        let mut cfg = Config::new();
+        cfg.add_language_variation("fr");
+        cfg.add_header_variation("testheader", "testvalue");
+
+        let languages = [
+            HeaderValue::from_static("de"),
+            HeaderValue::from_static("en"),
+        ];
+
+        let var: Vec<_> = cfg
+            .generate_header_variations_for(Some(&languages))
+            .collect();
+        assert_eq!(
+            var.len() as u64,
+            cfg.possible_variations_for(Some(&languages))
+        );
+
+        assert_eq!(
+            var[..],
+            [
+                hm(&[
+                    ("testheader", "testvalue"),
+                    ("user-agent", APP_USER_AGENT),
+                    ("accept-language", "de"),
+                ]),
+                hm(&[
+                    ("testheader", "testvalue"),
+                    ("user-agent", APP_USER_AGENT),
+                    ("accept-language", "en"),
+                ]),
+            ]
+        );
+    }
+
+    #[test]
+    fn variations_media() {
+        let mut cfg = Config::new();
+        cfg.add_language_variation("de");
+        cfg.add_language_variation("en");
+        cfg.add_header_variation("testheader", "testvalue");
+        cfg.add_media_header_variation("accept", "image/webp");
+        cfg.add_media_header_variation("accept", "image/avif");
+
+        let media = cfg.for_media();
+        let var: Vec<_> = media.generate_header_variations().collect();
+        assert_eq!(var.len() as u64, media.possible_variations());
+
+        assert_eq!(
+            var[..],
+            [
+                hm(&[("accept", "image/webp"), ("user-agent", APP_USER_AGENT)]),
+                hm(&[("accept", "image/avif"), ("user-agent", APP_USER_AGENT)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn variations_assets() {
+        let mut cfg = Config::new();
+        cfg.include_assets = true;
+        cfg.add_language_variation("de");
+        cfg.add_header_variation("testheader", "testvalue");
+        cfg.add_asset_header_variation("accept-encoding", "br");
+
+        let assets = cfg.for_assets();
+        assert!(!assets.include_assets);
+        assert_eq!(
+            assets.generate_header_variations().collect::<Vec<_>>()[..],
+            [hm(&[
+                ("accept-encoding", "br"),
+                ("user-agent", APP_USER_AGENT)
+            ])]
+        );
+    }
+
+    #[test]
+    fn variations_site() {
+        let mut cfg = Config::new();
+        cfg.add_language_variation("de");
+        cfg.add_header_variation("testheader", "testvalue");
+
+        let site = cfg.for_site(&[parse_header("x-device:mobile").unwrap()], &[]);
+        assert_eq!(
+            site.generate_header_variations().collect::<Vec<_>>()[..],
+            [hm(&[
+                ("x-device", "mobile"),
+                ("accept-language", "de"),
+                ("user-agent", APP_USER_AGENT)
+            ])]
+        );
+
+        let site = cfg.for_site(&[], &["fr".into()]);
+        assert_eq!(
+            site.generate_header_variations().collect::<Vec<_>>()[..],
+            [hm(&[
+                ("testheader", "testvalue"),
+                ("accept-language", "fr"),
+                ("user-agent", APP_USER_AGENT)
+            ])]
+        );
+    }
+
     #[test]
     fn variations_two_headers_two_values() {
         let mut cfg = Config::new();
@@ -0,0 +1,328 @@
+//! discovering pages by following links, for sites without sitemaps.
+use crate::{
+    client,
+    config::Config,
+    heater::{self, Limits},
+    retry::RetryPolicy,
+    sitemaps::Entry,
+};
+use futures::{
+    stream::{self, BoxStream},
+    StreamExt,
+};
+use reqwest::{header::HeaderMap, Client};
+use std::collections::HashSet;
+use tokio::sync::mpsc;
+use url::Url;
+
+/// the hosts and path prefixes the crawler stays in.
+#[derive(Debug, Clone)]
+struct Scope {
+    hosts: Vec<String>,
+    prefixes: Vec<String>,
+}
+
+impl Scope {
+    fn contains(&self, url: &Url) -> bool {
+        let host = url.host_str().unwrap_or("");
+        self.hosts.iter().any(|h| h.eq_ignore_ascii_case(host))
+            && (self.prefixes.is_empty() || self.prefixes.iter().any(|p| url.path().starts_with(p)))
+    }
+}
+
+/// crawl the site starting at the seed URL, returning every page found as
+/// soon as it is requested.
+///
+/// Pages are crawled breadth-first, up to `Config::crawl_max_depth` links
+/// away from the seed, and stopping after `Config::crawl_max_pages` pages.
+/// Only links on `Config::crawl_hosts` (the host of the seed by default) and
+/// below one of `Config::crawl_prefixes` are followed.
+/// Pages that can't be loaded are logged and skipped.
+/// The pages are requested like warming does, with its first header
+/// variation, and the entries carry the result so warming doesn't request
+/// them again. The pages of the last level, and those left at the deadline,
+/// aren't requested.
+/// The pages are fetched within the rate and concurrency limits of warming,
+/// and the crawl stops at its deadline.
+pub fn stream(config: &Config, seed: Url) -> BoxStream<'static, Entry> {
+    let scope = Scope {
+        hosts: if config.crawl_hosts.is_empty() {
+            seed.host_str().into_iter().map(str::to_owned).collect()
+        } else {
+            config.crawl_hosts.clone()
+        },
+        prefixes: config.crawl_prefixes.clone(),
+    };
+
+    let crawler = Crawler {
+        client: client::build(config),
+        limits: Limits::new(config),
+        headers: config
+            .generate_header_variations()
+            .next()
+            .unwrap_or_default(),
+        retry: config.retry.clone(),
+        find_assets: config.include_assets,
+        scope,
+        max_depth: config.crawl_max_depth,
+        max_pages: config.crawl_max_pages.max(1),
+        concurrency: config.concurrent_requests.max(1),
+    };
+
+    let (sender, receiver) = mpsc::channel(crawler.max_pages.min(1024));
+    tokio::spawn(crawler.run(seed, sender));
+
+    stream::unfold(receiver, |mut receiver| async move {
+        let entry = receiver.recv().await?;
+        Some((entry, receiver))
+    })
+    .boxed()
+}
+
+struct Crawler {
+    client: Client,
+    limits: Limits,
+    /// the first header variation of warming
+    headers: HeaderMap,
+    retry: RetryPolicy,
+    find_assets: bool,
+    scope: Scope,
+    max_depth: usize,
+    max_pages: usize,
+    concurrency: usize,
+}
+
+impl Crawler {
+    /// crawl level by level, sending every page to `found` once it is
+    /// requested, and the pages of the last level, or the ones left at the
+    /// deadline, at the end.
+    ///
+    /// Stops early when the receiving stream was dropped, or at the deadline.
+    async fn run(self, seed: Url, found: mpsc::Sender<Entry>) {
+        let mut seen: HashSet<Url> = HashSet::from([seed.clone()]);
+
+        let mut level = vec![seed];
+        'crawl: for depth in 1..=self.max_depth {
+            let mut next: Vec<Url> = Vec::new();
+
+            // `buffered` keeps the page order, so the crawl order is stable.
+            let mut pages = stream::iter(level.clone())
+                .map(|url| self.page(url))
+                .buffered(self.concurrency);
+
+            let mut requested = 0;
+            while let Some(page) = pages.next().await {
+                let Some((entry, links)) = page else {
+                    log::info!("crawl stopped at the deadline");
+                    level.drain(..requested);
+                    level.append(&mut next);
+                    break 'crawl;
+                };
+                requested += 1;
+                if found.send(entry).await.is_err() {
+                    return;
+                }
+                for link in links {
+                    if seen.len() >= self.max_pages {
+                        break;
+                    }
+                    if self.scope.contains(&link) && seen.insert(link.clone()) {
+                        next.push(link);
+                        if seen.len() == self.max_pages {
+                            log::info!("crawl stopped after {} pages", self.max_pages);
+                        }
+                    }
+                }
+            }
+
+            log::debug!("found {} pages at depth {depth}", next.len());
+            level = next;
+            if level.is_empty() {
+                return;
+            }
+        }
+
+        // the pages that were found but not requested
+        for url in level {
+            if found.send(Entry::from(url)).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// the entry of the page, with its result for warming, and the links of
+    /// the HTML page, empty for other content and errors.
+    /// `None` when the deadline passed before or while requesting it.
+    async fn page(&self, url: Url) -> Option<(Entry, Vec<Url>)> {
+        let (warmed, links) = heater::crawl_page(
+            &self.client,
+            &url,
+            &self.headers,
+            self.find_assets,
+            &self.retry,
+            &self.limits,
+        )
+        .await?;
+        let entry = Entry {
+            warmed: Some(warmed),
+            ..Entry::from(url)
+        };
+        Some((entry, links))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rate::RateLimiter;
+    use std::{sync::Arc, time::Instant};
+
+    async fn crawl(config: &Config, seed: String) -> Vec<String> {
+        stream(config, Url::parse(&seed).unwrap())
+            .map(|entry| entry.url.path().to_owned())
+            .collect()
+            .await
+    }
+
+    async fn site() -> mockito::ServerGuard {
+        let mut server = mockito::Server::new_async().await;
+        for (path, body) in [
+            (
+                "/",
+                r#"<a href="/docs/">docs</a> <a href="/blog/">blog</a>
+                   <a href="https://elsewhere.example.com/">other host</a>"#,
+            ),
+            ("/docs/", r#"<a href="/docs/a">a</a> <a href="/">home</a>"#),
+            ("/blog/", r#"<a href="/blog/post">post</a>"#),
+            ("/docs/a", r#"<a href="/docs/b">b</a>"#),
+            ("/blog/post", ""),
+            ("/docs/b", ""),
+        ] {
+            server
+                .mock("GET", path)
+                .with_status(200)
+                .with_header("content-type", "text/html; charset=utf-8")
+                .with_body(body)
+                .create_async()
+                .await;
+        }
+        server
+    }
+
+    #[tokio::test]
+    async fn crawl_site() {
+        let server = site().await;
+
+        assert_eq!(
+            crawl(&Config::new(), format!("{}/", server.url())).await,
+            ["/", "/docs/", "/blog/", "/docs/a", "/blog/post", "/docs/b"]
+        );
+    }
+
+    #[tokio::test]
+    async fn max_depth() {
+        let server = site().await;
+        let mut config = Config::new();
+        config.crawl_max_depth = 1;
+
+        assert_eq!(
+            crawl(&config, format!("{}/", server.url())).await,
+            ["/", "/docs/", "/blog/"]
+        );
+    }
+
+    #[tokio::test]
+    async fn max_pages() {
+        let server = site().await;
+        let mut config = Config::new();
+        config.crawl_max_pages = 4;
+
+        assert_eq!(
+            crawl(&config, format!("{}/", server.url())).await,
+            ["/", "/docs/", "/blog/", "/docs/a"]
+        );
+    }
+
+    #[tokio::test]
+    async fn prefixes() {
+        let server = site().await;
+        let mut config = Config::new();
+        config.crawl_prefixes = vec!["/docs/".into()];
+
+        assert_eq!(
+            crawl(&config, format!("{}/", server.url())).await,
+            ["/", "/docs/", "/docs/a", "/docs/b"]
+        );
+    }
+
+    #[tokio::test]
+    async fn rate_limited() {
+        let server = site().await;
+        let mut config = Config::new();
+        config.rate_limit = RateLimiter::new(Some("4/s".parse().unwrap()), &[]).map(Arc::new);
+
+        // 4 pages from the full bucket, the fifth one waits 250ms
+        let start = std::time::Instant::now();
+        assert_eq!(crawl(&config, format!("{}/", server.url())).await.len(), 6);
+        assert!(start.elapsed() >= std::time::Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn deadline() {
+        let server = site().await;
+        let mut config = Config::new();
+        config.rate_limit = RateLimiter::new(Some("1/s".parse().unwrap()), &[]).map(Arc::new);
+        config.deadline = Some(Instant::now() + std::time::Duration::from_millis(300));
+
+        // the second page would only be fetched after the deadline
+        let start = Instant::now();
+        assert_eq!(
+            crawl(&config, format!("{}/", server.url())).await,
+            ["/", "/docs/", "/blog/"]
+        );
+        assert!(start.elapsed() < std::time::Duration::from_millis(900));
+    }
+
+    #[tokio::test]
+    async fn skips_errors_and_other_content() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/")
+            .with_status(200)
+            .with_header("content-type", "text/html")
+            .with_body(r#"<a href="/missing">x</a> <a href="/data.json">y</a>"#)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/missing")
+            .with_status(404)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/data.json")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"html": "<a href=\"/hidden\">"}"#)
+            .create_async()
+            .await;
+
+        assert_eq!(
+            crawl(&Config::new(), format!("{}/", server.url())).await,
+            ["/", "/missing", "/data.json"]
+        );
+    }
+
+    #[test]
+    fn scope() {
+        let scope = Scope {
+            hosts: vec!["www.example.com".into()],
+            prefixes: vec!["/docs/".into(), "/blog/".into()],
+        };
+        let contains = |url: &str| scope.contains(&Url::parse(url).unwrap());
+
+        assert!(contains("https://www.example.com/docs/a"));
+        assert!(contains("http://WWW.example.com/blog/"));
+        assert!(!contains("https://www.example.com/shop/"));
+        assert!(!contains("https://cdn.example.com/docs/a"));
+    }
+}
@@ -0,0 +1,173 @@
+//! parsing of sitemap extensions that the `sitemap` crate doesn't support.
+use std::collections::HashMap;
+use url::Url;
+use xml::reader::{EventReader, XmlEvent};
+
+/// an alternate language version of a page, from
+/// `<xhtml:link rel="alternate" hreflang="..." href="..." />`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alternate {
+    pub hreflang: String,
+    pub url: Url,
+}
+
+/// the extension data of a single `<url>` entry.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct Extensions {
+    pub(crate) alternates: Vec<Alternate>,
+    /// image and video URLs from the image & video sitemap extensions
+    pub(crate) media: Vec<Url>,
+}
+
+/// element paths of media URLs in the image & video sitemap extensions.
+const MEDIA_PATHS: [[&str; 4]; 3] = [
+    ["urlset", "url", "image", "loc"],
+    ["urlset", "url", "video", "content_loc"],
+    ["urlset", "url", "video", "thumbnail_loc"],
+];
+
+/// collect the extension data of all `<url>` entries in an `urlset`,
+/// keyed by their `<loc>`.
+pub(crate) fn parse(body: &[u8]) -> Result<HashMap<Url, Extensions>, xml::reader::Error> {
+    let mut result: HashMap<Url, Extensions> = HashMap::new();
+
+    let mut path: Vec<String> = Vec::new();
+    let mut loc: Option<Url> = None;
+    let mut current = Extensions::default();
+
+    for event in EventReader::new(body) {
+        match event? {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => {
+                let name = name.local_name.to_lowercase();
+
+                if name == "link" && path == ["urlset", "url"] {
+                    let attribute = |key: &str| {
+                        attributes
+                            .iter()
+                            .find(|a| a.name.local_name == key)
+                            .map(|a| a.value.trim())
+                    };
+
+                    if let (Some("alternate"), Some(hreflang), Some(href)) =
+                        (attribute("rel"), attribute("hreflang"), attribute("href"))
+                    {
+                        match Url::parse(href) {
+                            Ok(url) => current.alternates.push(Alternate {
+                                hreflang: hreflang.to_owned(),
+                                url,
+                            }),
+                            Err(err) => log::warn!("could not parse alternate url: {err:?}"),
+                        }
+                    }
+                }
+
+                path.push(name);
+            }
+            XmlEvent::EndElement { .. } => {
+                if path == ["urlset", "url"] {
+                    if let Some(loc) = loc.take() {
+                        result.insert(loc, std::mem::take(&mut current));
+                    }
+                    current = Extensions::default();
+                }
+                path.pop();
+            }
+            XmlEvent::Characters(text) if path == ["urlset", "url", "loc"] => {
+                loc = Url::parse(text.trim()).ok();
+            }
+            XmlEvent::Characters(text) | XmlEvent::CData(text)
+                if MEDIA_PATHS.iter().any(|p| path == p) =>
+            {
+                match Url::parse(text.trim()) {
+                    Ok(url) => current.media.push(url),
+                    Err(err) => log::warn!("could not parse media url: {err:?}"),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alternates() {
+        let body = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9"
+                    xmlns:xhtml="http://www.w3.org/1999/xhtml">
+              <url>
+                <loc>https://www.example.com/en/</loc>
+                <xhtml:link rel="alternate" hreflang="en" href="https://www.example.com/en/" />
+                <xhtml:link rel="alternate" hreflang="de-CH" href="https://www.example.com/de/" />
+                <xhtml:link rel="alternate" hreflang="x-default" href="https://www.example.com/" />
+              </url>
+              <url>
+                <loc>https://www.example.com/other</loc>
+              </url>
+            </urlset>"#;
+
+        let result = parse(body.as_bytes()).unwrap();
+
+        assert_eq!(result.len(), 2);
+        assert_eq!(
+            result[&Url::parse("https://www.example.com/en/").unwrap()].alternates[..],
+            [
+                Alternate {
+                    hreflang: "en".into(),
+                    url: Url::parse("https://www.example.com/en/").unwrap(),
+                },
+                Alternate {
+                    hreflang: "de-CH".into(),
+                    url: Url::parse("https://www.example.com/de/").unwrap(),
+                },
+                Alternate {
+                    hreflang: "x-default".into(),
+                    url: Url::parse("https://www.example.com/").unwrap(),
+                },
+            ]
+        );
+        assert!(
+            result[&Url::parse("https://www.example.com/other").unwrap()]
+                .alternates
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn media() {
+        let body = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9"
+                    xmlns:image="http://www.google.com/schemas/sitemap-image/1.1"
+                    xmlns:video="http://www.google.com/schemas/sitemap-video/1.1">
+              <url>
+                <loc>https://www.example.com/page</loc>
+                <image:image>
+                  <image:loc>https://cdn.example.com/image.jpg</image:loc>
+                </image:image>
+                <video:video>
+                  <video:thumbnail_loc>https://cdn.example.com/thumb.jpg</video:thumbnail_loc>
+                  <video:title>a video</video:title>
+                  <video:content_loc>https://cdn.example.com/video.mp4</video:content_loc>
+                  <video:player_loc>https://www.example.com/player</video:player_loc>
+                </video:video>
+              </url>
+            </urlset>"#;
+
+        let result = parse(body.as_bytes()).unwrap();
+
+        assert_eq!(
+            result[&Url::parse("https://www.example.com/page").unwrap()].media[..],
+            [
+                Url::parse("https://cdn.example.com/image.jpg").unwrap(),
+                Url::parse("https://cdn.example.com/thumb.jpg").unwrap(),
+                Url::parse("https://cdn.example.com/video.mp4").unwrap(),
+            ]
+        );
+    }
+}
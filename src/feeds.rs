@@ -0,0 +1,121 @@
+use url::Url;
+use xml::reader::{EventReader, XmlEvent};
+
+/// root element names of RSS 2.0, RSS 1.0 (RDF) and Atom feeds.
+pub(crate) const FEED_ROOT_ELEMENTS: [&str; 3] = ["rss", "rdf", "feed"];
+
+/// extract the article URLs from an RSS or Atom feed.
+///
+/// For RSS these are the `<item><link>` texts, for Atom the `href` of
+/// `<entry><link>` elements that are alternates of the entry.
+pub(crate) fn parse(base: &Url, body: &[u8]) -> Result<Vec<Url>, xml::reader::Error> {
+    let mut result: Vec<Url> = Vec::new();
+    let mut path: Vec<String> = Vec::new();
+
+    let mut push = |link: &str| match base.join(link.trim()) {
+        Ok(url) => result.push(url),
+        Err(err) => log::warn!("could not parse feed url: {err:?}"),
+    };
+
+    for event in EventReader::new(body) {
+        match event? {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => {
+                let name = name.local_name.to_lowercase();
+
+                if name == "link" && path.last().map(String::as_str) == Some("entry") {
+                    let rel = attributes
+                        .iter()
+                        .find(|a| a.name.local_name == "rel")
+                        .map(|a| a.value.as_str());
+
+                    let href = attributes.iter().find(|a| a.name.local_name == "href");
+
+                    if let (None | Some("alternate"), Some(href)) = (rel, href) {
+                        push(&href.value);
+                    }
+                }
+
+                path.push(name);
+            }
+            XmlEvent::EndElement { .. } => {
+                path.pop();
+            }
+            XmlEvent::Characters(text) | XmlEvent::CData(text)
+                if path.ends_with(&["item".to_owned(), "link".to_owned()]) =>
+            {
+                push(&text);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base() -> Url {
+        Url::parse("https://www.example.com/feed.xml").unwrap()
+    }
+
+    #[test]
+    fn rss() {
+        let body = r#"<?xml version="1.0"?>
+            <rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom">
+              <channel>
+                <title>News</title>
+                <link>https://www.example.com/</link>
+                <atom:link href="https://www.example.com/feed.xml" rel="self" />
+                <item>
+                  <title>first</title>
+                  <link>https://www.example.com/first</link>
+                </item>
+                <item>
+                  <link><![CDATA[https://www.example.com/second]]></link>
+                </item>
+              </channel>
+            </rss>"#;
+
+        assert_eq!(
+            parse(&base(), body.as_bytes()).unwrap()[..],
+            [
+                Url::parse("https://www.example.com/first").unwrap(),
+                Url::parse("https://www.example.com/second").unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn atom() {
+        let body = r#"<?xml version="1.0" encoding="utf-8"?>
+            <feed xmlns="http://www.w3.org/2005/Atom">
+              <title>News</title>
+              <link href="https://www.example.com/" />
+              <entry>
+                <title>first</title>
+                <link href="https://www.example.com/first" />
+                <link rel="edit" href="https://www.example.com/edit/first" />
+              </entry>
+              <entry>
+                <link rel="alternate" href="/second" />
+              </entry>
+            </feed>"#;
+
+        assert_eq!(
+            parse(&base(), body.as_bytes()).unwrap()[..],
+            [
+                Url::parse("https://www.example.com/first").unwrap(),
+                Url::parse("https://www.example.com/second").unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(parse(&base(), b"<rss><channel>").is_err());
+    }
+}
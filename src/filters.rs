@@ -0,0 +1,438 @@
+use crate::{
+    config::{Config, Order, Sample},
+    sitemaps::{ChangeFreq, Entry},
+};
+use chrono::{DateTime, Utc};
+use rand::{rngs::StdRng, seq::index, SeedableRng};
+use regex::Regex;
+use std::{cmp::Reverse, collections::HashMap, str::FromStr};
+use url::Url;
+
+/// the sitemap protocol defines 0.5 as the default priority of a page.
+const DEFAULT_PRIORITY: f32 = 0.5;
+
+/// the part of the URL a pattern is matched against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UrlPart {
+    Path,
+    Query,
+    Host,
+}
+
+/// an `--include` / `--exclude` pattern.
+///
+/// Patterns look like `[path:|query:|host:][re:]PATTERN`. They match the
+/// path by default, and are globs unless prefixed with `re:`.
+/// Globs have to match the whole part, `*` and `?` don't match `/`, while
+/// `**` does. Regular expressions match anywhere in the part, unless anchored.
+#[derive(Debug, Clone)]
+pub struct UrlPattern {
+    part: UrlPart,
+    regex: Regex,
+}
+
+impl UrlPattern {
+    pub fn is_match(&self, url: &Url) -> bool {
+        let value = match self.part {
+            UrlPart::Path => url.path(),
+            UrlPart::Query => url.query().unwrap_or(""),
+            UrlPart::Host => url.host_str().unwrap_or(""),
+        };
+        self.regex.is_match(value)
+    }
+}
+
+impl FromStr for UrlPattern {
+    type Err = regex::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (part, pattern) = if let Some(pattern) = s.strip_prefix("path:") {
+            (UrlPart::Path, pattern)
+        } else if let Some(pattern) = s.strip_prefix("query:") {
+            (UrlPart::Query, pattern)
+        } else if let Some(pattern) = s.strip_prefix("host:") {
+            (UrlPart::Host, pattern)
+        } else {
+            (UrlPart::Path, s)
+        };
+
+        let regex = match pattern.strip_prefix("re:") {
+            Some(regex) => Regex::new(regex)?,
+            None => Regex::new(&glob_to_regex(pattern))?,
+        };
+
+        Ok(UrlPattern { part, regex })
+    }
+}
+
+/// translate a glob into an anchored regular expression.
+fn glob_to_regex(glob: &str) -> String {
+    let mut result = String::from("^");
+    let mut chars = glob.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                result.push_str(".*");
+            }
+            '*' => result.push_str("[^/]*"),
+            '?' => result.push_str("[^/]"),
+            c => result.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+
+    result.push('$');
+    result
+}
+
+/// check if the URL passes the configured include / exclude patterns.
+///
+/// Without include patterns all URLs are included, exclude patterns win over
+/// include patterns.
+pub fn is_url_match(config: &Config, url: &Url) -> bool {
+    let included = config.include.is_empty() || config.include.iter().any(|p| p.is_match(url));
+    let excluded = config.exclude.iter().any(|p| p.is_match(url));
+
+    included && !excluded
+}
+
+/// check if the entry passes the configured include / exclude patterns and
+/// lastmod / priority limits.
+///
+/// Entries without `lastmod` are kept when filtering by modification date,
+/// since we can't know if they are stale.
+pub fn is_match(config: &Config, entry: &Entry, now: DateTime<Utc>) -> bool {
+    let modified = match (config.modified_since, entry.lastmod) {
+        (Some(since), Some(lastmod)) => lastmod >= now - since,
+        _ => true,
+    };
+
+    let priority = match config.min_priority {
+        Some(min) => entry.priority.unwrap_or(DEFAULT_PRIORITY) >= min,
+        None => true,
+    };
+
+    is_url_match(config, &entry.url) && modified && priority
+}
+
+/// sort the entries in the configured order.
+///
+/// All sorts are stable, so the sitemap order is kept for equal entries.
+pub fn sort(config: &Config, entries: &mut [Entry]) {
+    match config.order {
+        Order::Sitemap => {}
+        Order::Priority => entries.sort_by(|a, b| {
+            let priority = |e: &Entry| e.priority.unwrap_or(DEFAULT_PRIORITY);
+            priority(b).total_cmp(&priority(a))
+        }),
+        Order::LastMod => entries.sort_by_key(|e| Reverse(e.lastmod)),
+        Order::ChangeFreq => {
+            entries.sort_by_key(|e| e.changefreq.as_ref().map(changefreq_rank).unwrap_or(7))
+        }
+    }
+}
+
+/// pick a random sample of the entries, keeping their order.
+///
+/// With `Config::sample_stratified`, every sitemap is sampled on its own,
+/// proportional to its size and with at least one entry each, so the total
+/// can be slightly above the requested count.
+/// Without `Config::sample_seed` a random seed is used, which is logged so the
+/// sample can be reproduced.
+pub fn sample(config: &Config, entries: Vec<Entry>) -> Vec<Entry> {
+    let Some(sample) = config.sample else {
+        return entries;
+    };
+
+    let seed = config.sample_seed.unwrap_or_else(rand::random);
+    log::info!("sampling URLs with seed {seed}");
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let total = entries.len();
+    let sample_size = |len: usize| match sample {
+        Sample::Count(count) if config.sample_stratified && total > 0 => {
+            (count * len / total).max(1)
+        }
+        Sample::Count(count) => count,
+        Sample::Percent(percent) => (len as f64 * percent / 100.0).ceil() as usize,
+    };
+
+    // the indexes of the entries per stratum, in sitemap order
+    let strata: Vec<Vec<usize>> = if config.sample_stratified {
+        let mut strata: Vec<Vec<usize>> = Vec::new();
+        let mut positions: HashMap<Option<&Url>, usize> = HashMap::new();
+        for (i, entry) in entries.iter().enumerate() {
+            let position = *positions.entry(entry.sitemap.as_ref()).or_insert_with(|| {
+                strata.push(Vec::new());
+                strata.len() - 1
+            });
+            strata[position].push(i);
+        }
+        strata
+    } else {
+        vec![(0..total).collect()]
+    };
+
+    let mut selected: Vec<usize> = strata
+        .iter()
+        .flat_map(|stratum| {
+            let amount = sample_size(stratum.len()).min(stratum.len());
+            index::sample(&mut rng, stratum.len(), amount)
+                .into_iter()
+                .map(|i| stratum[i])
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    selected.sort_unstable();
+
+    let mut selected = selected.into_iter().peekable();
+    entries
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| selected.next_if_eq(i).is_some())
+        .map(|(_, entry)| entry)
+        .collect()
+}
+
+/// rank change frequencies, the most frequently changing pages first.
+fn changefreq_rank(changefreq: &ChangeFreq) -> u8 {
+    match changefreq {
+        ChangeFreq::Always => 0,
+        ChangeFreq::Hourly => 1,
+        ChangeFreq::Daily => 2,
+        ChangeFreq::Weekly => 3,
+        ChangeFreq::Monthly => 4,
+        ChangeFreq::Yearly => 5,
+        ChangeFreq::Never => 6,
+        ChangeFreq::None | ChangeFreq::ParseErr(_) => 7,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+    use std::time::Duration;
+    use test_case::test_case;
+
+    fn entry(path: &str, lastmod: Option<&str>, priority: Option<f32>) -> Entry {
+        Entry {
+            url: Url::parse("https://www.example.com/")
+                .unwrap()
+                .join(path)
+                .unwrap(),
+            lastmod: lastmod.map(|l| DateTime::parse_from_rfc3339(l).unwrap()),
+            changefreq: None,
+            priority,
+            alternates: Vec::new(),
+            media: Vec::new(),
+            sitemap: None,
+            warmed: None,
+        }
+    }
+
+    fn apply_at(config: &Config, entries: Vec<Entry>, now: DateTime<Utc>) -> Vec<Entry> {
+        let mut entries: Vec<Entry> = entries
+            .into_iter()
+            .filter(|entry| is_match(config, entry, now))
+            .collect();
+
+        sort(config, &mut entries);
+
+        entries
+    }
+
+    fn paths(entries: &[Entry]) -> Vec<&str> {
+        entries.iter().map(|e| e.url.path()).collect()
+    }
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2021-10-10T00:00:00Z")
+            .unwrap()
+            .into()
+    }
+
+    fn entries() -> Vec<Entry> {
+        vec![
+            entry("/old", Some("2020-01-01T00:00:00Z"), Some(0.9)),
+            entry("/unknown", None, None),
+            entry("/new", Some("2021-10-09T00:00:00Z"), Some(0.3)),
+            entry("/newer", Some("2021-10-09T12:00:00Z"), Some(1.0)),
+        ]
+    }
+
+    #[test]
+    fn no_filters() {
+        let config = Config::new();
+        assert_eq!(
+            paths(&apply_at(&config, entries(), now())),
+            ["/old", "/unknown", "/new", "/newer"]
+        );
+    }
+
+    #[test]
+    fn modified_since() {
+        let mut config = Config::new();
+        config.modified_since = Some(Duration::from_secs(2 * 24 * 60 * 60));
+        assert_eq!(
+            paths(&apply_at(&config, entries(), now())),
+            ["/unknown", "/new", "/newer"]
+        );
+    }
+
+    #[test]
+    fn min_priority() {
+        let mut config = Config::new();
+        config.min_priority = Some(0.5);
+        assert_eq!(
+            paths(&apply_at(&config, entries(), now())),
+            ["/old", "/unknown", "/newer"]
+        );
+    }
+
+    #[test]
+    fn order_priority() {
+        let mut config = Config::new();
+        config.order = Order::Priority;
+        assert_eq!(
+            paths(&apply_at(&config, entries(), now())),
+            ["/newer", "/old", "/unknown", "/new"]
+        );
+    }
+
+    #[test]
+    fn order_lastmod() {
+        let mut config = Config::new();
+        config.order = Order::LastMod;
+        assert_eq!(
+            paths(&apply_at(&config, entries(), now())),
+            ["/newer", "/new", "/old", "/unknown"]
+        );
+    }
+
+    #[test]
+    fn order_changefreq() {
+        let mut config = Config::new();
+        config.order = Order::ChangeFreq;
+
+        let mut input = entries();
+        input[0].changefreq = Some(ChangeFreq::Never);
+        input[2].changefreq = Some(ChangeFreq::Hourly);
+        input[3].changefreq = Some(ChangeFreq::Daily);
+
+        assert_eq!(
+            paths(&apply_at(&config, input, now())),
+            ["/new", "/newer", "/old", "/unknown"]
+        );
+    }
+
+    #[test_case("/docs/**", "https://www.example.com/docs/a/b", true ; "double star")]
+    #[test_case("/docs/**", "https://www.example.com/docs", false ; "double star needs slash")]
+    #[test_case("/docs/*", "https://www.example.com/docs/a/b", false ; "star stops at slash")]
+    #[test_case("/page-?", "https://www.example.com/page-1", true ; "question mark")]
+    #[test_case("/search", "https://www.example.com/search?q=a", true ; "glob ignores query")]
+    #[test_case("/search", "https://www.example.com/search/more", false ; "glob is anchored")]
+    #[test_case("/a.b", "https://www.example.com/axb", false ; "glob escapes regex")]
+    #[test_case("re:^/(admin|search)", "https://www.example.com/admin/x", true ; "regex")]
+    #[test_case("re:admin", "https://www.example.com/x/admin/y", true ; "regex is not anchored")]
+    #[test_case("path:re:\\.pdf$", "https://www.example.com/file.pdf", true ; "explicit path")]
+    #[test_case("query:*utm_*", "https://www.example.com/?a=1&utm_source=x", true ; "query glob")]
+    #[test_case("query:re:^$", "https://www.example.com/", true ; "empty query")]
+    #[test_case("host:*.example.com", "https://cdn.example.com/", true ; "host glob")]
+    #[test_case("host:*.example.com", "https://example.com/", false ; "host glob mismatch")]
+    fn url_pattern(pattern: &str, url: &str, expected: bool) {
+        let pattern: UrlPattern = pattern.parse().unwrap();
+        assert_eq!(pattern.is_match(&Url::parse(url).unwrap()), expected);
+    }
+
+    #[test]
+    fn invalid_url_pattern() {
+        assert!("re:(".parse::<UrlPattern>().is_err());
+    }
+
+    #[test]
+    fn include_exclude() {
+        let mut config = Config::new();
+        config.include = vec!["/new*".parse().unwrap(), "/old".parse().unwrap()];
+        config.exclude = vec!["re:er$".parse().unwrap()];
+        assert_eq!(
+            paths(&apply_at(&config, entries(), now())),
+            ["/old", "/new"]
+        );
+    }
+
+    fn sitemap_entries() -> Vec<Entry> {
+        let sitemap = |name: &str| {
+            Some(
+                Url::parse("https://www.example.com/")
+                    .unwrap()
+                    .join(name)
+                    .unwrap(),
+            )
+        };
+        (0..100)
+            .map(|i| Entry {
+                sitemap: sitemap(if i < 90 { "/pages.xml" } else { "/news.xml" }),
+                warmed: None,
+                ..entry(&format!("/{i}"), None, None)
+            })
+            .collect()
+    }
+
+    fn sample_with(sample: Sample, stratified: bool, seed: u64) -> Vec<Entry> {
+        let mut config = Config::new();
+        config.sample = Some(sample);
+        config.sample_stratified = stratified;
+        config.sample_seed = Some(seed);
+        self::sample(&config, sitemap_entries())
+    }
+
+    #[test]
+    fn no_sample() {
+        assert_eq!(sample(&Config::new(), sitemap_entries()).len(), 100);
+    }
+
+    #[test_case(Sample::Count(10), 10)]
+    #[test_case(Sample::Count(1000), 100 ; "more than available")]
+    #[test_case(Sample::Percent(5.0), 5)]
+    #[test_case(Sample::Percent(0.5), 1 ; "rounds up")]
+    fn sample_size(sample: Sample, expected: usize) {
+        assert_eq!(sample_with(sample, false, 1).len(), expected);
+    }
+
+    #[test]
+    fn sample_keeps_order() {
+        let sampled = sample_with(Sample::Count(20), false, 1);
+        let mut sorted = sampled.clone();
+        sorted.sort_by_key(|e| e.url.path()[1..].parse::<usize>().unwrap());
+        assert_eq!(sampled, sorted);
+    }
+
+    #[test]
+    fn sample_seed() {
+        assert_eq!(
+            sample_with(Sample::Count(10), false, 42),
+            sample_with(Sample::Count(10), false, 42)
+        );
+        assert_ne!(
+            sample_with(Sample::Count(10), false, 42),
+            sample_with(Sample::Count(10), false, 43)
+        );
+    }
+
+    #[test_case(Sample::Count(5), 4, 1)]
+    #[test_case(Sample::Count(20), 18, 2)]
+    #[test_case(Sample::Percent(10.0), 9, 1)]
+    fn sample_stratified(sample: Sample, pages: usize, news: usize) {
+        let sampled = sample_with(sample, true, 1);
+        let count = |name: &str| {
+            sampled
+                .iter()
+                .filter(|e| e.sitemap.as_ref().unwrap().path() == name)
+                .count()
+        };
+        assert_eq!(count("/pages.xml"), pages);
+        assert_eq!(count("/news.xml"), news);
+    }
+}
@@ -1,54 +1,589 @@
-use crate::{config::Config, status};
+use crate::{
+    adaptive::{self, AdaptiveLimit, Outcome},
+    assets, client,
+    config::Config,
+    html,
+    rate::RateLimiter,
+    retry::{self, RetryPolicy},
+    status,
+    targets::Target,
+};
+use chrono::Utc;
 use counter::Counter;
-use futures::{stream, StreamExt};
+use futures::{stream, Stream, StreamExt};
 use histogram::Histogram;
-use itertools::iproduct;
 use reqwest::{
     header::{self, HeaderMap, HeaderName},
-    Client, IntoUrl, StatusCode,
+    Client, StatusCode,
+};
+use std::{
+    collections::HashSet,
+    error::Error as _,
+    fmt,
+    future::{self, Future},
+    io,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
-use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use url::Url;
 
-pub async fn heat<T: 'static + IntoUrl + Send + Clone>(
-    config: &Config,
-    urls: impl Iterator<Item = T>,
-) -> (Counter<StatusCode>, Counter<Option<bool>>, Histogram) {
-    let client = Client::builder().gzip(true).build().unwrap();
+/// the kinds of errors of failed requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ErrorKind {
+    Timeout,
+    Dns,
+    Connect,
+    Tls,
+    /// reading or decoding the response body failed
+    Body,
+    /// too many redirects
+    RedirectLoop,
+    Other,
+}
+
+impl ErrorKind {
+    pub const NAMES: [&'static str; 7] = [
+        "timeout",
+        "dns",
+        "connect",
+        "tls",
+        "body",
+        "redirect-loop",
+        "other",
+    ];
+
+    pub fn of(err: &reqwest::Error) -> Self {
+        if err.is_timeout() {
+            return ErrorKind::Timeout;
+        }
+        if err.is_redirect() {
+            return ErrorKind::RedirectLoop;
+        }
+        if err.is_body() || err.is_decode() {
+            return ErrorKind::Body;
+        }
+
+        // reqwest doesn't tell DNS & TLS errors apart from other connect
+        // errors, but their sources do
+        let mut source = err.source();
+        while let Some(err) = source {
+            if err.is::<client::DnsError>() {
+                return ErrorKind::Dns;
+            }
+            if err.is::<native_tls::Error>() {
+                return ErrorKind::Tls;
+            }
+            if let Some(err) = err.downcast_ref::<io::Error>()
+                && let Some(kind) = ErrorKind::of_io(err.kind())
+            {
+                return kind;
+            }
+            source = err.source();
+        }
+
+        if err.is_connect() {
+            ErrorKind::Connect
+        } else {
+            ErrorKind::Other
+        }
+    }
+
+    fn of_io(kind: io::ErrorKind) -> Option<Self> {
+        match kind {
+            io::ErrorKind::TimedOut => Some(ErrorKind::Timeout),
+            io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::HostUnreachable
+            | io::ErrorKind::NetworkUnreachable
+            | io::ErrorKind::AddrNotAvailable => Some(ErrorKind::Connect),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("invalid error kind: {0}")]
+pub struct InvalidErrorKind(String);
+
+impl FromStr for ErrorKind {
+    type Err = InvalidErrorKind;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "timeout" => Ok(ErrorKind::Timeout),
+            "dns" => Ok(ErrorKind::Dns),
+            "connect" => Ok(ErrorKind::Connect),
+            "tls" => Ok(ErrorKind::Tls),
+            "body" => Ok(ErrorKind::Body),
+            "redirect-loop" => Ok(ErrorKind::RedirectLoop),
+            "other" => Ok(ErrorKind::Other),
+            _ => Err(InvalidErrorKind(s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            ErrorKind::Timeout => "timeout",
+            ErrorKind::Dns => "DNS",
+            ErrorKind::Connect => "connect",
+            ErrorKind::Tls => "TLS",
+            ErrorKind::Body => "body",
+            ErrorKind::RedirectLoop => "redirect loop",
+            ErrorKind::Other => "other",
+        })
+    }
+}
+
+/// a request that failed without a response.
+#[derive(Debug)]
+pub struct Failure {
+    pub url: Url,
+    pub kind: ErrorKind,
+    pub message: String,
+    /// how often the request was retried before giving up.
+    pub retries: usize,
+}
 
-    stream::iter(iproduct!(urls, config.generate_header_variations()))
-        .map(|(url, hm)| {
+/// the outcome of warming a set of URLs.
+#[derive(Debug)]
+pub struct Results {
+    pub statuses: Counter<StatusCode>,
+    /// the failed requests by kind, which have no status.
+    pub errors: Counter<ErrorKind>,
+    /// the failed requests, in the order they failed.
+    pub failures: Vec<Failure>,
+    /// the number of retries of all requests.
+    pub retries: usize,
+    /// the retries by URL, of all its header variations.
+    pub retried: Counter<Url>,
+    pub cache_hits: Counter<Option<bool>>,
+    pub histogram: Histogram,
+    /// the subresources found in HTML pages with `Config::include_assets`,
+    /// each only once, in the order they were found.
+    pub assets: Vec<Url>,
+    /// the pages whose body could not be read for finding their assets,
+    /// their status is counted nonetheless.
+    pub asset_errors: usize,
+    /// how the concurrency was adapted with `Config::adaptive`.
+    pub adaptive: Option<adaptive::Summary>,
+    /// the requests that were still running at `Config::deadline`, they
+    /// have no result.
+    pub cancelled: usize,
+    /// whether URLs were left unwarmed or cancelled at `Config::deadline`.
+    pub deadline_reached: bool,
+}
+
+impl Results {
+    fn new() -> Self {
+        Results {
+            statuses: Counter::new(),
+            errors: Counter::new(),
+            failures: Vec::new(),
+            retries: 0,
+            retried: Counter::new(),
+            cache_hits: Counter::new(),
+            histogram: Histogram::new(10, 30).expect("could not initialize histogram"),
+            assets: Vec::new(),
+            asset_errors: 0,
+            adaptive: None,
+            cancelled: 0,
+            deadline_reached: false,
+        }
+    }
+}
+
+pub async fn heat<T: Into<Target>>(config: &Config, urls: impl Iterator<Item = T>) -> Results {
+    heat_stream(config, stream::iter(urls)).await
+}
+
+/// like `heat`, but for URLs that are still being discovered.
+pub async fn heat_stream<T: Into<Target>>(config: &Config, urls: impl Stream<Item = T>) -> Results {
+    let client = client::build(config);
+
+    // most targets use the configured variations, so we only generate them once.
+    let default_variations: Vec<HeaderMap> = config.generate_header_variations().collect();
+
+    let requests = urls.map(Into::into).flat_map(|target: Target| {
+        let variations = variations(config, &target, &default_variations);
+        // the first variation may already be warmed by the crawler
+        let mut warmed = target.warmed.as_ref().and_then(Warmed::take);
+        stream::iter(
+            variations
+                .into_iter()
+                .map(move |hm| (target.url.clone(), hm, warmed.take())),
+        )
+    });
+
+    let find = Find {
+        assets: config.include_assets,
+        links: false,
+    };
+    let limits = Limits::new(config);
+
+    // stop scheduling requests, and discovering their URLs, at the deadline
+    let requests = requests.take_until(limits.at_deadline());
+    let retry = Arc::new(config.retry.clone());
+    let mut seen_assets: HashSet<Url> = HashSet::new();
+
+    let mut results = requests
+        .map(|(url, hm, warmed)| {
             let client = client.clone();
-            tokio::spawn(async move { heat_one(&client, url, hm).await })
+            let limits = limits.clone();
+            let retry = retry.clone();
+            tokio::spawn(async move {
+                let heated = match warmed {
+                    Some(heated) => heated,
+                    None => heat_with_retries(&client, &url, &hm, find, &retry, &limits).await,
+                };
+                (url, heated)
+            })
         })
         .buffer_unordered(config.concurrent_requests)
-        .map(|result| {
-            result // while tokio join errors should always panic,
-                .unwrap_or_else(|err| panic!("tokio error: {:?}", err))
-                // TODO: reqwest errors should be handled differently
-                .unwrap_or_else(|err| panic!("reqwest error error: {:?}", err))
+        // while tokio join errors should always panic,
+        .map(|result| result.unwrap_or_else(|err| panic!("tokio error: {:?}", err)))
+        .fold(Results::new(), |mut results, (url, heated)| {
+            let (result, retries) = match heated {
+                Heated::Done(result, retries) => {
+                    if let Some(st) = status::get_progress() {
+                        st.inc(1);
+                    }
+                    (result, retries)
+                }
+                Heated::Skipped => return futures::future::ready(results),
+                Heated::Cancelled => {
+                    results.cancelled += 1;
+                    return futures::future::ready(results);
+                }
+            };
+
+            if retries > 0 {
+                results.retries += retries;
+                results.retried[&url] += retries;
+            }
+
+            let Response {
+                status,
+                cache_hit,
+                elapsed,
+                assets,
+                assets_failed,
+                ..
+            } = match result {
+                Ok(response) => response,
+                Err(err) => {
+                    let kind = ErrorKind::of(&err);
+                    log::warn!("warming {url} failed: {kind} error");
+                    results.errors[&kind] += 1;
+                    results.failures.push(Failure {
+                        url,
+                        kind,
+                        message: error_message(&err.without_url()),
+                        retries,
+                    });
+                    return futures::future::ready(results);
+                }
+            };
+
+            results.statuses[&status] += 1;
+            results.cache_hits[&cache_hit] += 1;
+            results
+                .histogram
+                .increment(elapsed.as_millis() as u64)
+                .unwrap();
+
+            if assets_failed {
+                results.asset_errors += 1;
+            }
+            for asset in assets {
+                if seen_assets.insert(asset.clone()) {
+                    results.assets.push(asset);
+                }
+            }
+
+            futures::future::ready(results)
         })
-        .fold(
-            (
-                Counter::new(),
-                Counter::new(),
-                Histogram::new(10, 30).expect("could not initialize histogram"),
-            ),
-            |(mut acc_status, mut acc_cache, mut histogram), (status, cache_hit, elapsed)| async move {
-                acc_status[&status] += 1;
-                acc_cache[&cache_hit] += 1;
-                histogram.increment(elapsed.as_millis() as u64).unwrap();
-
-                (acc_status, acc_cache, histogram)
-            },
-        )
-        .await
+        .await;
+
+    results.deadline_reached = limits.deadline_reached();
+    results.adaptive = limits.adaptive.map(|limit| limit.summary());
+    results
+}
+
+/// the error with all its sources, like `error sending request: client error (Connect): ...`.
+fn error_message(err: &reqwest::Error) -> String {
+    let mut message = err.to_string();
+    let mut source = err.source();
+    while let Some(err) = source {
+        message.push_str(": ");
+        message.push_str(&err.to_string());
+        source = err.source();
+    }
+    message
+}
+
+/// the header variations the target is warmed with, `default_variations` are
+/// the ones of the config.
+pub(crate) fn variations(
+    config: &Config,
+    target: &Target,
+    default_variations: &[HeaderMap],
+) -> Vec<HeaderMap> {
+    let mut variations: Vec<HeaderMap> = match target.languages {
+        Some(ref languages) => config
+            .generate_header_variations_for(Some(languages))
+            .collect(),
+        None => default_variations.to_vec(),
+    };
+    if let Some(ref host) = target.host {
+        for hm in variations.iter_mut() {
+            hm.insert(header::HOST, host.clone());
+        }
+    }
+    variations
+}
+
+/// the limits a request waits for, shared by all requests of a stream. Used
+/// for warming and for crawling.
+#[derive(Clone)]
+pub(crate) struct Limits {
+    /// shared with the other sites that are warmed at the same time
+    request: Option<Arc<Semaphore>>,
+    rate: Option<Arc<RateLimiter>>,
+    adaptive: Option<Arc<AdaptiveLimit>>,
+    /// no requests are started after it, and running ones are cancelled.
+    deadline: Option<Instant>,
+    /// whether a request was skipped or cancelled at the deadline.
+    deadline_reached: Arc<AtomicBool>,
 }
 
-async fn heat_one<T: IntoUrl>(
+/// the permits of a request, released when dropped.
+pub(crate) struct Permits<'a> {
+    adaptive: Option<adaptive::Permit<'a>>,
+    _request: Option<SemaphorePermit<'a>>,
+}
+
+impl Limits {
+    pub(crate) fn new(config: &Config) -> Self {
+        Limits {
+            request: config.request_limit.clone(),
+            rate: config.rate_limit.clone(),
+            adaptive: config
+                .adaptive
+                .clone()
+                .map(|policy| Arc::new(AdaptiveLimit::new(policy, config.concurrent_requests))),
+            deadline: config.deadline,
+            deadline_reached: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// wait until a request to the URL is allowed, first by the rate limit
+    /// and then by the concurrency limits. `None` when the deadline passed
+    /// before.
+    pub(crate) async fn acquire(&self, url: &Url) -> Option<Permits<'_>> {
+        let permits = self
+            .until_deadline(async {
+                if let Some(ref limit) = self.rate {
+                    limit.acquire(url).await;
+                }
+                let adaptive = match self.adaptive {
+                    Some(ref limit) => Some(limit.acquire().await),
+                    None => None,
+                };
+                let request = match self.request {
+                    Some(ref limit) => Some(limit.acquire().await.expect("closed request limit")),
+                    None => None,
+                };
+                Permits {
+                    adaptive,
+                    _request: request,
+                }
+            })
+            .await?;
+
+        // the permits might only be granted at the deadline
+        if self.before_deadline(Instant::now()) {
+            Some(permits)
+        } else {
+            self.deadline_reached.store(true, Ordering::Relaxed);
+            None
+        }
+    }
+
+    /// run the future until the deadline, `None` when it passed first.
+    pub(crate) async fn until_deadline<F: Future>(&self, future: F) -> Option<F::Output> {
+        let result = match self.deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline.into(), future).await.ok(),
+            None => Some(future.await),
+        };
+        if result.is_none() {
+            self.deadline_reached.store(true, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// completes at the deadline, for stopping a stream, never without one.
+    async fn at_deadline(&self) {
+        match self.deadline {
+            Some(deadline) => {
+                tokio::time::sleep_until(deadline.into()).await;
+                self.deadline_reached.store(true, Ordering::Relaxed);
+            }
+            None => future::pending().await,
+        }
+    }
+
+    /// whether anything was skipped or cancelled at the deadline.
+    pub(crate) fn deadline_reached(&self) -> bool {
+        self.deadline_reached.load(Ordering::Relaxed)
+    }
+
+    /// whether the instant is before the deadline.
+    fn before_deadline(&self, instant: Instant) -> bool {
+        self.deadline.is_none_or(|deadline| instant < deadline)
+    }
+}
+
+impl Permits<'_> {
+    /// release the permits, adapting the concurrency to the outcome.
+    pub(crate) fn finish(self, outcome: Outcome) {
+        if let Some(permit) = self.adaptive {
+            permit.finish(outcome);
+        }
+    }
+}
+
+/// what is looked for in the responses, besides warming them.
+#[derive(Debug, Clone, Copy)]
+struct Find {
+    /// the subresources of HTML pages and `Link` headers
+    assets: bool,
+    /// the links of successful HTML pages
+    links: bool,
+}
+
+/// the response of a warming request.
+#[derive(Debug)]
+struct Response {
+    status: StatusCode,
+    cache_hit: Option<bool>,
+    elapsed: Duration,
+    assets: Vec<Url>,
+    /// whether reading the body for finding the assets failed.
+    assets_failed: bool,
+    links: Vec<Url>,
+    retry_after: Option<Duration>,
+}
+
+/// how warming a single URL ended.
+#[derive(Debug)]
+enum Heated {
+    /// the last result, with the number of retries
+    Done(Result<Response, reqwest::Error>, usize),
+    /// the deadline passed before the first attempt
+    Skipped,
+    /// the deadline passed while requesting
+    Cancelled,
+}
+
+/// warm a single URL, retrying it like the policy says.
+///
+/// The concurrency limits are only held while requesting, not while waiting
+/// for the next retry. Every attempt counts against the rate limit, which is
+/// waited for first. At the deadline the running request is cancelled, and a
+/// retry that didn't start yet is given up for the last result.
+async fn heat_with_retries(
+    client: &Client,
+    url: &Url,
+    headers: &HeaderMap,
+    find: Find,
+    retry: &RetryPolicy,
+    limits: &Limits,
+) -> Heated {
+    let mut retries: usize = 0;
+    // the result that is retried
+    let mut last: Option<Result<Response, reqwest::Error>> = None;
+    loop {
+        let Some(permits) = limits.acquire(url).await else {
+            return match last {
+                Some(result) => Heated::Done(result, retries),
+                None => {
+                    log::debug!("skipping {url} after the deadline");
+                    Heated::Skipped
+                }
+            };
+        };
+        if last.take().is_some() {
+            retries += 1;
+        }
+
+        let request = heat_one(client, url.clone(), headers.clone(), find);
+        let Some(result) = limits.until_deadline(request).await else {
+            log::debug!("cancelled {url} at the deadline");
+            return Heated::Cancelled;
+        };
+        permits.finish(outcome(&result));
+
+        // `Some` with the `Retry-After` when the request should be retried
+        let retry_after = match result {
+            Ok(ref response) if retry.retries_status(response.status) => Some(response.retry_after),
+            Err(ref err) if retry.retries_error(ErrorKind::of(err)) => Some(None),
+            _ => None,
+        };
+
+        // no retries that would start after the deadline
+        let delay = retry_after
+            .filter(|_| retries < retry.retries)
+            .map(|retry_after| retry.delay(retries as u32, retry_after))
+            .filter(|delay| limits.before_deadline(Instant::now() + *delay));
+
+        match delay {
+            Some(delay) => {
+                log::info!(
+                    "retrying {url} in {delay:?}, retry {}/{}",
+                    retries + 1,
+                    retry.retries
+                );
+                if limits
+                    .until_deadline(tokio::time::sleep(delay))
+                    .await
+                    .is_none()
+                {
+                    return Heated::Done(result, retries);
+                }
+                last = Some(result);
+            }
+            None => return Heated::Done(result, retries),
+        }
+    }
+}
+
+/// whether the result shows an overloaded origin.
+fn outcome(result: &Result<Response, reqwest::Error>) -> Outcome {
+    match result {
+        Ok(response) => Outcome::of_response(response.status, response.elapsed),
+        Err(err) => Outcome::of_error(ErrorKind::of(err)),
+    }
+}
+
+/// warm a single URL, returning what `find` asks for too.
+async fn heat_one(
     client: &Client,
-    url: T,
+    url: Url,
     headers: HeaderMap,
-) -> Result<(StatusCode, Option<bool>, Duration), reqwest::Error> {
+    find: Find,
+) -> Result<Response, reqwest::Error> {
     let start = Instant::now();
 
     let mut request = client.get(url);
@@ -56,7 +591,7 @@ async fn heat_one<T: IntoUrl>(
         request = request.header(h, v);
     }
 
-    let result = match request.send().await {
+    match request.send().await {
         Ok(response) => {
             let duration = start.elapsed();
 
@@ -85,24 +620,136 @@ async fn heat_one<T: IntoUrl>(
             let cache_hit = response
                 .headers()
                 .get(HeaderName::from_static("x-cache"))
-                .map(|value| value.to_str().unwrap_or("")[0..3].to_lowercase() == "hit");
+                .map(|value| {
+                    value
+                        .as_bytes()
+                        .get(..3)
+                        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(b"hit"))
+                });
+
+            let status = response.status();
+            let retry_after = response
+                .headers()
+                .get(header::RETRY_AFTER)
+                .and_then(|value| retry::parse_retry_after(value, Utc::now()));
+            // the page was warmed even when its body can't be read
+            let (assets, assets_failed, links) = if find.assets || find.links {
+                let url = response.url().clone();
+                match read_page(response, find).await {
+                    Ok((assets, links)) => (assets, false, links),
+                    Err(err) => {
+                        log::warn!(
+                            "could not read {url}: {}",
+                            error_message(&err.without_url())
+                        );
+                        (Vec::new(), find.assets, Vec::new())
+                    }
+                }
+            } else {
+                (Vec::new(), false, Vec::new())
+            };
 
-            Ok((response.status(), cache_hit, duration))
+            Ok(Response {
+                status,
+                cache_hit,
+                elapsed: duration,
+                assets,
+                assets_failed,
+                links,
+                retry_after,
+            })
         }
         Err(err) => Err(err),
+    }
+}
+
+/// the subresources and links of the response, as `find` asks for them. The
+/// assets are from its `Link` headers and the body of HTML pages, the links
+/// only from the body of successful HTML pages.
+async fn read_page(
+    response: reqwest::Response,
+    find: Find,
+) -> Result<(Vec<Url>, Vec<Url>), reqwest::Error> {
+    let url = response.url().clone();
+    let mut assets = if find.assets {
+        assets::from_link_headers(&url, response.headers())
+    } else {
+        Vec::new()
     };
 
-    if let Some(st) = status::get_progress() {
-        st.inc(1);
+    let is_html = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("html"));
+    let find_links = find.links && response.status().is_success();
+
+    let mut links = Vec::new();
+    if is_html && (find.assets || find_links) {
+        let body = response.text().await?;
+        if find.assets {
+            assets.extend(assets::from_html(&url, &body));
+        }
+        if find_links {
+            links = html::links(&url, &body);
+        }
     }
 
-    result
+    Ok((assets, links))
+}
+
+/// request a page for the crawler, with the first header variation of the
+/// config, so warming can use the result instead of requesting it again.
+///
+/// Returns the links of successful HTML pages, or `None` when the deadline
+/// passed before or while requesting it.
+pub(crate) async fn crawl_page(
+    client: &Client,
+    url: &Url,
+    headers: &HeaderMap,
+    find_assets: bool,
+    retry: &RetryPolicy,
+    limits: &Limits,
+) -> Option<(Warmed, Vec<Url>)> {
+    let find = Find {
+        assets: find_assets,
+        links: true,
+    };
+    let mut heated = heat_with_retries(client, url, headers, find, retry, limits).await;
+    let links = match heated {
+        Heated::Done(Ok(ref mut response), _) => std::mem::take(&mut response.links),
+        Heated::Done(Err(ref err), _) => {
+            log::warn!("could not crawl {url}: {}", ErrorKind::of(err));
+            Vec::new()
+        }
+        Heated::Skipped | Heated::Cancelled => return None,
+    };
+    Some((Warmed(Arc::new(Mutex::new(Some(heated)))), links))
+}
+
+/// the result of the request the crawler made for a page, so warming doesn't
+/// request it again. Taken by the first target it is warmed for.
+#[derive(Debug, Clone)]
+pub struct Warmed(Arc<Mutex<Option<Heated>>>);
+
+impl Warmed {
+    fn take(&self) -> Option<Heated> {
+        self.0.lock().unwrap().take()
+    }
+}
+
+impl PartialEq for Warmed {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
 }
 
+impl Eq for Warmed {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config;
+    use crate::{adaptive::AdaptivePolicy, config};
     use reqwest::{self, Url};
     use test_case::test_case;
 
@@ -125,7 +772,11 @@ mod tests {
         let urls: Vec<Url> = vec![Url::parse(&format!("{}/dummy.xml", server.url())).unwrap()];
 
         let config = Config::new();
-        let (statuses, cdn, _) = heat(&config, urls.iter().cloned()).await;
+        let Results {
+            statuses,
+            cache_hits: cdn,
+            ..
+        } = heat(&config, urls.iter().cloned()).await;
 
         m.assert();
 
@@ -136,7 +787,10 @@ mod tests {
     }
 
     #[test_case("HIT", true)]
+    #[test_case("Hit from cloudfront", true)]
     #[test_case("MISS", false)]
+    #[test_case("H", false)]
+    #[test_case("", false)]
     #[tokio::test]
     async fn heat_single_page_cdn(header_value: &str, expected: bool) {
         let mut server = mockito::Server::new_async().await;
@@ -150,7 +804,11 @@ mod tests {
         let urls: Vec<Url> = vec![Url::parse(&format!("{}/dummy.xml", server.url())).unwrap()];
 
         let config = Config::new();
-        let (statuses, cdn, _) = heat(&config, urls.iter().cloned()).await;
+        let Results {
+            statuses,
+            cache_hits: cdn,
+            ..
+        } = heat(&config, urls.iter().cloned()).await;
 
         m.assert();
 
@@ -179,7 +837,11 @@ mod tests {
 
         let urls: Vec<Url> = vec![Url::parse(&format!("{}/dummy.xml", server.url())).unwrap()];
 
-        let (statuses, cdn, _) = heat(&config, urls.iter().cloned()).await;
+        let Results {
+            statuses,
+            cache_hits: cdn,
+            ..
+        } = heat(&config, urls.iter().cloned()).await;
 
         m.assert();
 
@@ -188,4 +850,341 @@ mod tests {
         assert_eq!(cdn.len(), 1);
         assert_eq!(cdn.get(&None), Some(&1));
     }
+
+    #[tokio::test]
+    async fn heat_target_languages() {
+        let mut server = mockito::Server::new_async().await;
+        let mocks = [
+            server
+                .mock("GET", "/dummy.xml")
+                .match_header(header::ACCEPT_LANGUAGE.as_str(), "de")
+                .with_status(200)
+                .create_async()
+                .await,
+            server
+                .mock("GET", "/dummy.xml")
+                .match_header(header::ACCEPT_LANGUAGE.as_str(), "fr")
+                .with_status(200)
+                .create_async()
+                .await,
+        ];
+
+        let mut config = Config::new();
+        config.add_language_variation("en");
+
+        let targets = vec![Target {
+            languages: Some(vec![
+                header::HeaderValue::from_static("de"),
+                header::HeaderValue::from_static("fr"),
+            ]),
+            ..Target::from(Url::parse(&format!("{}/dummy.xml", server.url())).unwrap())
+        }];
+
+        let Results { statuses, .. } = heat(&config, targets.into_iter()).await;
+
+        for m in mocks {
+            m.assert();
+        }
+        assert_eq!(statuses.get(&StatusCode::OK), Some(&2));
+    }
+
+    #[tokio::test]
+    async fn heat_target_host_header() {
+        let mut server = mockito::Server::new_async().await;
+        let m = server
+            .mock("GET", "/dummy.xml")
+            .match_header("host", "www.example.com")
+            .with_status(200)
+            .create_async()
+            .await;
+
+        let targets = vec![Target {
+            host: Some(header::HeaderValue::from_static("www.example.com")),
+            ..Target::from(Url::parse(&format!("{}/dummy.xml", server.url())).unwrap())
+        }];
+
+        let Results { statuses, .. } = heat(&Config::new(), targets.into_iter()).await;
+
+        m.assert();
+        assert_eq!(statuses.get(&StatusCode::OK), Some(&1));
+    }
+
+    #[tokio::test]
+    async fn heat_page_assets() {
+        let mut server = mockito::Server::new_async().await;
+        let _page = server
+            .mock("GET", "/page")
+            .with_status(200)
+            .with_header("content-type", "text/html")
+            .with_header("link", "</style.css>; rel=preload; as=style")
+            .with_body(r#"<link rel="stylesheet" href="/style.css"><img src="/image.png">"#)
+            .create_async()
+            .await;
+        let _other = server
+            .mock("GET", "/other")
+            .with_status(200)
+            .with_header("content-type", "text/html")
+            .with_body(r#"<script src="/app.js"></script><img src="/image.png">"#)
+            .create_async()
+            .await;
+
+        let urls = [
+            Url::parse(&format!("{}/page", server.url())).unwrap(),
+            Url::parse(&format!("{}/other", server.url())).unwrap(),
+        ];
+
+        let mut config = Config::new();
+        let results = heat(&config, urls.iter().cloned()).await;
+        assert!(results.assets.is_empty());
+
+        config.include_assets = true;
+        config.concurrent_requests = 1;
+        let results = heat(&config, urls.iter().cloned()).await;
+
+        let asset = |path: &str| Url::parse(&format!("{}{path}", server.url())).unwrap();
+        assert_eq!(
+            results.assets[..],
+            [asset("/style.css"), asset("/image.png"), asset("/app.js")]
+        );
+    }
+
+    #[tokio::test]
+    async fn heat_page_assets_truncated() {
+        // answers with an HTML page that ends before its content length
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/page", listener.local_addr().unwrap())).unwrap();
+        std::thread::spawn(move || {
+            use std::io::{Read, Write};
+            let (mut socket, _) = listener.accept().unwrap();
+            let _ = socket.read(&mut [0; 1024]).unwrap();
+            socket
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\ncontent-type: text/html\r\ncontent-length: 100\r\n\r\n<img src=",
+                )
+                .unwrap();
+        });
+
+        let mut config = Config::new();
+        config.include_assets = true;
+        let results = heat(&config, [url].into_iter()).await;
+
+        assert_eq!(results.statuses.get(&StatusCode::OK), Some(&1));
+        assert!(results.errors.is_empty());
+        assert!(results.assets.is_empty());
+        assert_eq!(results.asset_errors, 1);
+    }
+
+    #[test_case("closed", ErrorKind::Connect ; "connection refused")]
+    #[test_case("dns", ErrorKind::Dns ; "unknown host")]
+    #[test_case("silent", ErrorKind::Timeout ; "timeout")]
+    #[test_case("loop", ErrorKind::RedirectLoop ; "redirect loop")]
+    #[test_case("tls", ErrorKind::Tls ; "TLS handshake")]
+    #[tokio::test]
+    async fn heat_request_errors(case: &str, expected: ErrorKind) {
+        let mut server = mockito::Server::new_async().await;
+        let _ok = server
+            .mock("GET", "/ok")
+            .with_status(200)
+            .create_async()
+            .await;
+        let _loop = server
+            .mock("GET", "/loop")
+            .with_status(302)
+            .with_header("location", "/loop")
+            .create_async()
+            .await;
+
+        // accepts connections, but never answers
+        let silent = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap()
+        };
+
+        let failing = match case {
+            "closed" => format!("http://{closed}/"),
+            // never resolves, without depending on the system resolver
+            "dns" => "http://heater-test.invalid/".to_owned(),
+            "silent" => format!("http://{}/", silent.local_addr().unwrap()),
+            "loop" => format!("{}/loop", server.url()),
+            "tls" => format!("https://{}/ok", server.host_with_port()),
+            _ => unreachable!(),
+        };
+        let urls = [
+            Url::parse(&failing).unwrap(),
+            Url::parse(&format!("{}/ok", server.url())).unwrap(),
+        ];
+
+        let mut config = Config::new();
+        config.timeout = Some(Duration::from_millis(500));
+        let results = heat(&config, urls.iter().cloned()).await;
+
+        assert_eq!(results.statuses.get(&StatusCode::OK), Some(&1));
+        assert_eq!(results.errors.get(&expected), Some(&1));
+        assert_eq!(results.failures.len(), 1);
+        assert_eq!(results.failures[0].url, urls[0]);
+        assert_eq!(results.failures[0].kind, expected);
+        assert!(!results.failures[0].message.is_empty());
+    }
+
+    #[tokio::test]
+    async fn heat_retries() {
+        let mut server = mockito::Server::new_async().await;
+        // mocks with remaining hits are matched first
+        let unavailable = server
+            .mock("GET", "/page")
+            .with_status(503)
+            // the backoff would take longer than the test
+            .with_header("retry-after", "0")
+            .expect(2)
+            .create_async()
+            .await;
+        let ok = server
+            .mock("GET", "/page")
+            .with_status(200)
+            .expect(1)
+            .create_async()
+            .await;
+        let not_found = server
+            .mock("GET", "/missing")
+            .with_status(404)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let page = Url::parse(&format!("{}/page", server.url())).unwrap();
+        let missing = Url::parse(&format!("{}/missing", server.url())).unwrap();
+
+        let mut config = Config::new();
+        config.retry.retries = 3;
+        config.retry.delay = Duration::from_secs(60);
+        let results = heat(&config, [page.clone(), missing].into_iter()).await;
+
+        unavailable.assert_async().await;
+        ok.assert_async().await;
+        not_found.assert_async().await;
+
+        assert_eq!(results.statuses.get(&StatusCode::OK), Some(&1));
+        assert_eq!(results.statuses.get(&StatusCode::NOT_FOUND), Some(&1));
+        assert_eq!(results.statuses.get(&StatusCode::SERVICE_UNAVAILABLE), None);
+        assert_eq!(results.retries, 2);
+        assert_eq!(results.retried.get(&page), Some(&2));
+        assert_eq!(results.retried.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn heat_retries_exhausted() {
+        let closed = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap()
+        };
+        let url = Url::parse(&format!("http://{closed}/")).unwrap();
+
+        let mut config = Config::new();
+        config.retry.retries = 2;
+        config.retry.delay = Duration::from_millis(1);
+        let results = heat(&config, [url.clone()].into_iter()).await;
+
+        assert_eq!(results.errors.get(&ErrorKind::Connect), Some(&1));
+        assert_eq!(results.retries, 2);
+        assert_eq!(results.failures[0].url, url);
+        assert_eq!(results.failures[0].retries, 2);
+    }
+
+    #[tokio::test]
+    async fn heat_adaptive() {
+        let mut server = mockito::Server::new_async().await;
+        let unavailable = server
+            .mock("GET", mockito::Matcher::Any)
+            .with_status(503)
+            .expect(20)
+            .create_async()
+            .await;
+
+        let urls = (0..20).map(|i| Url::parse(&format!("{}/page/{i}", server.url())).unwrap());
+
+        let mut config = Config::new();
+        config.concurrent_requests = 4;
+        config.adaptive = Some(AdaptivePolicy {
+            breaker_error_rate: 0.5,
+            breaker_pause: Duration::from_millis(50),
+        });
+        let start = Instant::now();
+        let results = heat(&config, urls).await;
+
+        unavailable.assert_async().await;
+        assert_eq!(
+            results.statuses.get(&StatusCode::SERVICE_UNAVAILABLE),
+            Some(&20)
+        );
+        assert_eq!(
+            results.adaptive,
+            Some(adaptive::Summary {
+                max: 4,
+                lowest: 1,
+                breaker_opened: 2
+            })
+        );
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn heat_deadline() {
+        let mut server = mockito::Server::new_async().await;
+        let unavailable = server
+            .mock("GET", "/page")
+            .with_status(503)
+            .expect(1)
+            .create_async()
+            .await;
+        let page = Url::parse(&format!("{}/page", server.url())).unwrap();
+
+        // the retry would start after the deadline
+        let mut config = Config::new();
+        config.retry.retries = 3;
+        config.retry.delay = Duration::from_secs(60);
+        config.deadline = Some(Instant::now() + Duration::from_secs(10));
+        let results = heat(&config, [page.clone()].into_iter()).await;
+
+        unavailable.assert_async().await;
+        assert_eq!(
+            results.statuses.get(&StatusCode::SERVICE_UNAVAILABLE),
+            Some(&1)
+        );
+        assert_eq!(results.retries, 0);
+        assert!(!results.deadline_reached);
+
+        // nothing is started after the deadline
+        config.deadline = Some(Instant::now());
+        let results = heat(&config, [page].into_iter()).await;
+
+        unavailable.assert_async().await;
+        assert!(results.statuses.is_empty());
+        assert!(results.deadline_reached);
+    }
+
+    #[tokio::test]
+    async fn heat_deadline_cancels() {
+        // accepts connections, but never answers
+        let silent = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/", silent.local_addr().unwrap())).unwrap();
+
+        let mut config = Config::new();
+        config.deadline = Some(Instant::now() + Duration::from_millis(200));
+        let start = Instant::now();
+        let results = heat(&config, [url].into_iter()).await;
+
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert_eq!(results.cancelled, 1);
+        assert!(results.statuses.is_empty());
+        assert!(results.errors.is_empty());
+    }
+
+    #[test]
+    fn error_kind_names() {
+        for name in ErrorKind::NAMES {
+            assert!(name.parse::<ErrorKind>().is_ok());
+        }
+        assert!("unknown".parse::<ErrorKind>().is_err());
+    }
 }
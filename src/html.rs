@@ -0,0 +1,302 @@
+//! a minimal HTML tag scanner, good enough to find links in pages.
+//!
+//! It doesn't build a document tree, it only returns the start tags with
+//! their attributes, skipping comments and the contents of scripts & styles.
+use url::Url;
+
+/// an HTML start tag, with lowercase names.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Tag {
+    pub(crate) name: String,
+    attributes: Vec<(String, String)>,
+}
+
+impl Tag {
+    /// the (entity decoded) value of the attribute.
+    pub(crate) fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// the start tags of the document, in document order.
+pub(crate) fn tags(body: &str) -> Vec<Tag> {
+    // ASCII lowercasing keeps all byte positions
+    let lower = body.to_ascii_lowercase();
+    let bytes = body.as_bytes();
+    let mut result: Vec<Tag> = Vec::new();
+    let mut pos = 0;
+
+    while let Some(offset) = lower[pos..].find('<') {
+        let start = pos + offset + 1;
+
+        if lower[start..].starts_with("!--") {
+            match lower[start..].find("-->") {
+                Some(end) => pos = start + end + 3,
+                None => break,
+            }
+            continue;
+        }
+
+        // end tags, doctypes and stray `<`
+        if !bytes.get(start).is_some_and(u8::is_ascii_alphabetic) {
+            pos = start;
+            continue;
+        }
+
+        let (tag, end) = parse_tag(body, start);
+        pos = end;
+
+        // the contents of scripts & styles are not HTML
+        if tag.name == "script" || tag.name == "style" {
+            let close = format!("</{}", tag.name);
+            pos = lower[pos..]
+                .find(&close)
+                .map_or(body.len(), |end| pos + end);
+        }
+
+        result.push(tag);
+    }
+
+    result
+}
+
+/// the `<a href>` links of the page, resolved against its URL or `<base href>`.
+///
+/// Only HTTP(S) links are returned, without fragments. Links with
+/// `rel="nofollow"` are skipped.
+pub(crate) fn links(url: &Url, body: &str) -> Vec<Url> {
+    let tags = tags(body);
+    let base = base(url, &tags);
+
+    tags.iter()
+        .filter(|tag| tag.name == "a")
+        .filter(|tag| {
+            !tag.attribute("rel")
+                .is_some_and(|rel| rel.split_ascii_whitespace().any(|r| r == "nofollow"))
+        })
+        .filter_map(|tag| resolve(&base, tag.attribute("href")?))
+        .collect()
+}
+
+/// the URL relative links are resolved against, the page URL or `<base href>`.
+pub(crate) fn base(url: &Url, tags: &[Tag]) -> Url {
+    tags.iter()
+        .find(|tag| tag.name == "base")
+        .and_then(|tag| tag.attribute("href"))
+        .and_then(|href| url.join(href).ok())
+        .unwrap_or_else(|| url.clone())
+}
+
+/// resolve a link against the base URL, for HTTP(S) links only.
+pub(crate) fn resolve(base: &Url, href: &str) -> Option<Url> {
+    let mut url = base.join(href.trim()).ok()?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return None;
+    }
+    url.set_fragment(None);
+    Some(url)
+}
+
+/// parse the tag starting at `start` (after the `<`), returns the tag and the
+/// position after it.
+fn parse_tag(body: &str, start: usize) -> (Tag, usize) {
+    let bytes = body.as_bytes();
+    let is_name_end = |b: u8| b.is_ascii_whitespace() || b == b'/' || b == b'>' || b == b'=';
+
+    let mut pos = start;
+    while pos < bytes.len() && !is_name_end(bytes[pos]) {
+        pos += 1;
+    }
+    let name = body[start..pos].to_ascii_lowercase();
+    let mut attributes: Vec<(String, String)> = Vec::new();
+
+    loop {
+        while pos < bytes.len() && (bytes[pos].is_ascii_whitespace() || bytes[pos] == b'/') {
+            pos += 1;
+        }
+        if pos >= bytes.len() {
+            break;
+        }
+        if bytes[pos] == b'>' {
+            pos += 1;
+            break;
+        }
+
+        let name_start = pos;
+        while pos < bytes.len() && !is_name_end(bytes[pos]) {
+            pos += 1;
+        }
+        // a stray `=` without a name
+        if pos == name_start {
+            pos += 1;
+            continue;
+        }
+        let attribute = body[name_start..pos].to_ascii_lowercase();
+
+        while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        let mut value = String::new();
+        if pos < bytes.len() && bytes[pos] == b'=' {
+            pos += 1;
+            while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            let (raw, end) = match bytes.get(pos) {
+                Some(&quote @ (b'"' | b'\'')) => {
+                    let value_start = pos + 1;
+                    let value_end = body[value_start..]
+                        .find(quote as char)
+                        .map_or(body.len(), |end| value_start + end);
+                    (&body[value_start..value_end], value_end + 1)
+                }
+                _ => {
+                    let value_start = pos;
+                    while pos < bytes.len()
+                        && !bytes[pos].is_ascii_whitespace()
+                        && bytes[pos] != b'>'
+                    {
+                        pos += 1;
+                    }
+                    (&body[value_start..pos], pos)
+                }
+            };
+            value = decode_entities(raw);
+            pos = end.min(body.len());
+        }
+
+        attributes.push((attribute, value));
+    }
+
+    (Tag { name, attributes }, pos)
+}
+
+/// decode the character references that commonly appear in URLs.
+fn decode_entities(value: &str) -> String {
+    if !value.contains('&') {
+        return value.to_owned();
+    }
+
+    let mut result = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let decoded = rest.find(';').and_then(|end| {
+            let character = match &rest[1..end] {
+                "amp" => Some('&'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                entity => match entity.strip_prefix('#') {
+                    Some(hex) if hex.starts_with(['x', 'X']) => u32::from_str_radix(&hex[1..], 16)
+                        .ok()
+                        .and_then(char::from_u32),
+                    Some(decimal) => decimal.parse().ok().and_then(char::from_u32),
+                    None => None,
+                },
+            }?;
+            Some((character, end + 1))
+        });
+
+        match decoded {
+            Some((character, length)) => {
+                result.push(character);
+                rest = &rest[length..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn base() -> Url {
+        Url::parse("https://www.example.com/dir/page.html").unwrap()
+    }
+
+    #[test]
+    fn parse_tags() {
+        let body = r#"<!DOCTYPE html>
+            <HTML lang=en>
+            <!-- <a href="/commented"> -->
+            <script>if (a <b) { document.write('<a href="/script">') }</script>
+            <img src='/image.png' alt="a > b" hidden>
+            <br/>
+            </HTML>"#;
+
+        let tags = tags(body);
+
+        assert_eq!(
+            tags.iter().map(|t| t.name.as_str()).collect::<Vec<_>>(),
+            ["html", "script", "img", "br"]
+        );
+        assert_eq!(tags[0].attribute("lang"), Some("en"));
+        assert_eq!(tags[2].attribute("src"), Some("/image.png"));
+        assert_eq!(tags[2].attribute("alt"), Some("a > b"));
+        assert_eq!(tags[2].attribute("hidden"), Some(""));
+        assert_eq!(tags[2].attribute("missing"), None);
+    }
+
+    #[test]
+    fn unterminated() {
+        assert_eq!(tags("<a href=\"/x").len(), 1);
+        assert_eq!(tags("<a href=").len(), 1);
+        assert!(tags("<!-- x").is_empty());
+        assert!(tags("a < b").is_empty());
+    }
+
+    #[test_case("/a?x=1&amp;y=2", "/a?x=1&y=2")]
+    #[test_case("&#47;a&#x2F;b", "/a/b")]
+    #[test_case("a & b &unknown; &", "a & b &unknown; &")]
+    fn entities(input: &str, expected: &str) {
+        assert_eq!(decode_entities(input), expected);
+    }
+
+    #[test]
+    fn find_links() {
+        let body = r##"
+            <a href="other.html#section">relative</a>
+            <a href="/absolute?a=1&amp;b=2">absolute</a>
+            <a href="https://other.example.com/">other host</a>
+            <a href="mailto:info@example.com">mail</a>
+            <a href="#top">fragment</a>
+            <a rel="external nofollow" href="/nofollow">nofollow</a>
+            <a name="anchor">no href</a>
+        "##;
+
+        assert_eq!(
+            links(&base(), body)[..],
+            [
+                Url::parse("https://www.example.com/dir/other.html").unwrap(),
+                Url::parse("https://www.example.com/absolute?a=1&b=2").unwrap(),
+                Url::parse("https://other.example.com/").unwrap(),
+                Url::parse("https://www.example.com/dir/page.html").unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn base_href() {
+        let body = r#"<head><base href="/other/"></head><a href="page">x</a>"#;
+
+        assert_eq!(
+            links(&base(), body)[..],
+            [Url::parse("https://www.example.com/other/page").unwrap()]
+        );
+    }
+}
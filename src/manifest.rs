@@ -0,0 +1,169 @@
+//! the multi-site manifest, to warm many sites with their own sitemaps,
+//! header variations and languages in one run.
+//!
+//! ```toml
+//! [[site]]
+//! name = "shop"
+//! sitemaps = ["https://shop.example.com/sitemap.xml"]
+//! headers = ["x-device:mobile", "x-device:desktop"]
+//! languages = ["de", "en"]
+//! ```
+use crate::{
+    config::{self, Config},
+    site::Site,
+};
+use serde::Deserialize;
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("could not read manifest")]
+    Read(#[from] std::io::Error),
+
+    #[error("invalid manifest")]
+    Parse(#[from] toml::de::Error),
+
+    #[error("the manifest contains no sites")]
+    NoSites,
+
+    #[error("site {0} has no sitemaps")]
+    NoSitemaps(String),
+
+    #[error("invalid header {header} for site {site}")]
+    InvalidHeader {
+        site: String,
+        header: String,
+        #[source]
+        source: config::Error,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    #[serde(default, rename = "site")]
+    sites: Vec<SiteEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SiteEntry {
+    name: Option<String>,
+    sitemaps: Vec<String>,
+    #[serde(default)]
+    headers: Vec<String>,
+    #[serde(default)]
+    languages: Vec<String>,
+}
+
+/// read the sites from the manifest file.
+pub fn load(path: &Path, config: &Config) -> Result<Vec<Site>, Error> {
+    parse(&std::fs::read_to_string(path)?, config)
+}
+
+/// parse the manifest, the sites are based on the given config.
+///
+/// Sites without a name are named after their first sitemap.
+fn parse(manifest: &str, config: &Config) -> Result<Vec<Site>, Error> {
+    let manifest: Manifest = toml::from_str(manifest)?;
+    if manifest.sites.is_empty() {
+        return Err(Error::NoSites);
+    }
+
+    manifest
+        .sites
+        .into_iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            let name = entry
+                .name
+                .or_else(|| entry.sitemaps.first().cloned())
+                .unwrap_or_else(|| format!("#{}", i + 1));
+
+            if entry.sitemaps.is_empty() {
+                return Err(Error::NoSitemaps(name));
+            }
+
+            let headers = entry
+                .headers
+                .iter()
+                .map(|header| {
+                    config::parse_header(header).map_err(|source| Error::InvalidHeader {
+                        site: name.clone(),
+                        header: header.clone(),
+                        source,
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            Ok(Site {
+                config: config.for_site(&headers, &entry.languages),
+                name: Some(name),
+                sources: entry.sitemaps,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_sites() {
+        let manifest = r#"
+            [[site]]
+            name = "shop"
+            sitemaps = ["https://shop.example.com/sitemap.xml", "https://shop.example.com/news.xml"]
+            headers = ["x-device:mobile", "x-device:desktop"]
+            languages = ["de", "en"]
+
+            [[site]]
+            sitemaps = ["https://www.example.com/"]
+        "#;
+
+        let mut config = Config::new();
+        config.concurrent_requests = 3;
+        let sites = parse(manifest, &config).unwrap();
+
+        assert_eq!(sites.len(), 2);
+
+        assert_eq!(sites[0].name.as_deref(), Some("shop"));
+        assert_eq!(
+            sites[0].sources,
+            [
+                "https://shop.example.com/sitemap.xml",
+                "https://shop.example.com/news.xml"
+            ]
+        );
+        // 2 devices × (de, en, "de, en", "en, de")
+        assert_eq!(sites[0].config.possible_variations(), 8);
+        assert_eq!(sites[0].config.concurrent_requests, 3);
+
+        assert_eq!(sites[1].name.as_deref(), Some("https://www.example.com/"));
+        assert_eq!(sites[1].config.possible_variations(), 1);
+    }
+
+    #[test]
+    fn invalid_manifests() {
+        let config = Config::new();
+
+        assert!(matches!(parse("", &config), Err(Error::NoSites)));
+        assert!(matches!(
+            parse("[[site]]\nsitemaps = []", &config),
+            Err(Error::NoSitemaps(_))
+        ));
+        assert!(matches!(
+            parse(
+                "[[site]]\nsitemaps = [\"a\"]\nheaders = [\"nocolon\"]",
+                &config
+            ),
+            Err(Error::InvalidHeader { .. })
+        ));
+        assert!(matches!(
+            parse("[[site]]\nsitemap = \"a\"", &config),
+            Err(Error::Parse(_))
+        ));
+    }
+}
@@ -0,0 +1,346 @@
+//! printing the planned requests of a dry run.
+use crate::{
+    config::Config,
+    heater,
+    site::{Plan, Site},
+    targets::Target,
+};
+use console::style;
+use reqwest::header::HeaderMap;
+use serde::Serialize;
+use std::{collections::BTreeMap, io::Write, str::FromStr};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("invalid format: {0}")]
+    InvalidFormat(String),
+
+    #[error("could not write the plan")]
+    Write(#[from] std::io::Error),
+
+    #[error("could not serialize the plan")]
+    Json(#[from] serde_json::Error),
+}
+
+/// the output format of the plan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// one tab separated line per request
+    #[default]
+    Text,
+    /// a single JSON document
+    Json,
+    /// one JSON object per line
+    Ndjson,
+}
+
+impl Format {
+    pub const NAMES: [&'static str; 3] = ["text", "json", "ndjson"];
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "ndjson" => Ok(Format::Ndjson),
+            _ => Err(Error::InvalidFormat(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Kind {
+    Page,
+    Media,
+}
+
+impl Kind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Kind::Page => "page",
+            Kind::Media => "media",
+        }
+    }
+}
+
+/// a planned request, with all the headers that will be sent.
+#[derive(Debug, Serialize)]
+struct Request<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    site: Option<&'a str>,
+    kind: Kind,
+    url: &'a str,
+    headers: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Totals {
+    pages: usize,
+    media: usize,
+    requests: usize,
+    filtered: usize,
+    skipped_sitemaps: usize,
+}
+
+/// write the planned requests of the sites, followed by the totals.
+pub fn write(out: &mut impl Write, format: Format, sites: &[(&Site, Plan)]) -> Result<(), Error> {
+    let mut totals = Totals::default();
+    let mut json_requests: Vec<Request> = Vec::new();
+
+    for (i, (site, plan)) in sites.iter().enumerate() {
+        totals.pages += plan.pages.len();
+        totals.media += plan.media.len();
+        totals.filtered += plan.filtered_count;
+        totals.skipped_sitemaps += plan.skipped_sitemaps.len();
+
+        if format == Format::Text && sites.len() > 1 {
+            if i > 0 {
+                writeln!(out)?;
+            }
+            let name = site.name.as_deref().unwrap_or("site");
+            writeln!(out, "{}", style(format!("== {name} ==")).bold())?;
+        }
+
+        for request in requests(site, plan) {
+            totals.requests += 1;
+            match format {
+                Format::Text => {
+                    write!(out, "{}\t{}", request.kind.as_str(), request.url)?;
+                    for (name, value) in &request.headers {
+                        write!(out, "\t{name}: {value}")?;
+                    }
+                    writeln!(out)?;
+                }
+                Format::Json => json_requests.push(request),
+                Format::Ndjson => {
+                    serde_json::to_writer(&mut *out, &request)?;
+                    writeln!(out)?;
+                }
+            }
+        }
+    }
+
+    match format {
+        Format::Text => {
+            writeln!(out)?;
+            writeln!(out, "{}", style("Totals").bold())?;
+            writeln!(out, "\t{:<18} {:>7}", "Pages:", totals.pages)?;
+            writeln!(out, "\t{:<18} {:>7}", "Media:", totals.media)?;
+            writeln!(out, "\t{:<18} {:>7}", "Requests:", totals.requests)?;
+            writeln!(out, "\t{:<18} {:>7}", "Filtered URLs:", totals.filtered)?;
+            if totals.skipped_sitemaps > 0 {
+                writeln!(
+                    out,
+                    "\t{:<18} {:>7}",
+                    "Skipped sitemaps:", totals.skipped_sitemaps
+                )?;
+            }
+        }
+        Format::Json => {
+            #[derive(Serialize)]
+            struct Document<'a> {
+                requests: Vec<Request<'a>>,
+                totals: Totals,
+            }
+
+            serde_json::to_writer_pretty(
+                &mut *out,
+                &Document {
+                    requests: json_requests,
+                    totals,
+                },
+            )?;
+            writeln!(out)?;
+        }
+        Format::Ndjson => {
+            #[derive(Serialize)]
+            struct Line {
+                totals: Totals,
+            }
+
+            serde_json::to_writer(&mut *out, &Line { totals })?;
+            writeln!(out)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// the requests of the site, pages before media, in the order they would be
+/// warmed.
+fn requests<'a>(site: &'a Site, plan: &'a Plan) -> impl Iterator<Item = Request<'a>> {
+    let media_config = site.config.for_media();
+
+    target_requests(site, &site.config, &plan.pages, Kind::Page).chain(target_requests(
+        site,
+        &media_config,
+        &plan.media,
+        Kind::Media,
+    ))
+}
+
+fn target_requests<'a>(
+    site: &'a Site,
+    config: &Config,
+    targets: &'a [Target],
+    kind: Kind,
+) -> impl Iterator<Item = Request<'a>> + use<'a> {
+    let default_variations: Vec<HeaderMap> = config.generate_header_variations().collect();
+    let config = config.clone();
+
+    targets.iter().flat_map(move |target| {
+        heater::variations(&config, target, &default_variations)
+            .into_iter()
+            .map(move |variation| Request {
+                site: site.name.as_deref(),
+                kind,
+                url: target.url.as_str(),
+                headers: headers(&site.config.request_headers, variation),
+            })
+    })
+}
+
+/// the headers of a request, the variation replaces the request headers of
+/// the same name. Sensitive values are redacted.
+fn headers(request_headers: &HeaderMap, variation: HeaderMap) -> BTreeMap<String, String> {
+    let mut headers = request_headers.clone();
+    headers.extend(variation);
+
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if value.is_sensitive() {
+                "<redacted>".to_owned()
+            } else {
+                String::from_utf8_lossy(value.as_bytes()).into_owned()
+            };
+            (name.as_str().to_owned(), value)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{self, APP_USER_AGENT};
+    use reqwest::header::{self, HeaderValue};
+    use url::Url;
+
+    fn site(name: Option<&str>) -> Site {
+        let mut config = Config::new();
+        config.add_language_variation("de");
+        config.add_language_variation("en");
+        config.request_headers.insert(
+            header::AUTHORIZATION,
+            config::parse_basic_auth("user:secret").unwrap(),
+        );
+        config
+            .request_headers
+            .insert(header::ACCEPT_LANGUAGE, HeaderValue::from_static("fr"));
+
+        Site {
+            name: name.map(str::to_owned),
+            sources: Vec::new(),
+            config,
+        }
+    }
+
+    fn plan() -> Plan {
+        let page = |url: &str| Target::from(Url::parse(url).unwrap());
+        Plan {
+            pages: vec![
+                page("https://www.example.com/"),
+                page("https://www.example.com/a"),
+            ],
+            media: vec![page("https://www.example.com/image.jpg")],
+            filtered_count: 3,
+            skipped_sitemaps: Vec::new(),
+            sitemap_error: None,
+        }
+    }
+
+    fn write_string(format: Format, sites: &[(&Site, Plan)]) -> String {
+        let mut out: Vec<u8> = Vec::new();
+        write(&mut out, format, sites).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn text() {
+        console::set_colors_enabled(false);
+        let site = site(None);
+        let output = write_string(Format::Text, &[(&site, plan())]);
+
+        let user_agent = format!("user-agent: {APP_USER_AGENT}");
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(
+            lines[..],
+            [
+                format!("page\thttps://www.example.com/\taccept-language: de\tauthorization: <redacted>\t{user_agent}"),
+                format!("page\thttps://www.example.com/\taccept-language: de, en\tauthorization: <redacted>\t{user_agent}"),
+                format!("page\thttps://www.example.com/\taccept-language: en, de\tauthorization: <redacted>\t{user_agent}"),
+                format!("page\thttps://www.example.com/\taccept-language: en\tauthorization: <redacted>\t{user_agent}"),
+                format!("page\thttps://www.example.com/a\taccept-language: de\tauthorization: <redacted>\t{user_agent}"),
+                format!("page\thttps://www.example.com/a\taccept-language: de, en\tauthorization: <redacted>\t{user_agent}"),
+                format!("page\thttps://www.example.com/a\taccept-language: en, de\tauthorization: <redacted>\t{user_agent}"),
+                format!("page\thttps://www.example.com/a\taccept-language: en\tauthorization: <redacted>\t{user_agent}"),
+                // media have no language variations
+                format!("media\thttps://www.example.com/image.jpg\taccept-language: fr\tauthorization: <redacted>\t{user_agent}"),
+                String::new(),
+                "Totals".to_owned(),
+                "\tPages:                   2".to_owned(),
+                "\tMedia:                   1".to_owned(),
+                "\tRequests:                9".to_owned(),
+                "\tFiltered URLs:           3".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn json() {
+        let one = site(Some("one"));
+        let two = site(Some("two"));
+        let output = write_string(Format::Json, &[(&one, plan()), (&two, plan())]);
+
+        let document: serde_json::Value = serde_json::from_str(&output).unwrap();
+        let requests = document["requests"].as_array().unwrap();
+        assert_eq!(requests.len(), 18);
+        assert_eq!(requests[0]["site"], "one");
+        assert_eq!(requests[0]["kind"], "page");
+        assert_eq!(requests[0]["url"], "https://www.example.com/");
+        assert_eq!(requests[0]["headers"]["accept-language"], "de");
+        assert_eq!(requests[17]["site"], "two");
+        assert_eq!(requests[17]["kind"], "media");
+
+        assert_eq!(
+            document["totals"],
+            serde_json::json!({
+                "pages": 4,
+                "media": 2,
+                "requests": 18,
+                "filtered": 6,
+                "skipped_sitemaps": 0,
+            })
+        );
+    }
+
+    #[test]
+    fn ndjson() {
+        let site = site(None);
+        let output = write_string(Format::Ndjson, &[(&site, plan())]);
+
+        let lines: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 10);
+        assert!(lines[0].get("site").is_none());
+        assert_eq!(lines[8]["url"], "https://www.example.com/image.jpg");
+        assert_eq!(lines[9]["totals"]["requests"], 9);
+    }
+}
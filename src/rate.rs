@@ -0,0 +1,268 @@
+//! limiting the warming requests per second, globally and per host.
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+use url::Url;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum Error {
+    #[error("invalid rate {0}, expected like 50/s, 600/m or 1000/h")]
+    InvalidRate(String),
+
+    #[error("missing rate in {0}, expected HOST=RATE")]
+    MissingRate(String),
+}
+
+/// a number of requests per second, parsed from `50/s`, `600/m` or `1000/h`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rate(f64);
+
+impl Rate {
+    pub fn per_second(&self) -> f64 {
+        self.0
+    }
+}
+
+impl FromStr for Rate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidRate(s.to_owned());
+
+        let (count, unit) = s.trim().split_once('/').unwrap_or((s.trim(), "s"));
+        let seconds = match unit {
+            "s" | "sec" => 1.0,
+            "m" | "min" => 60.0,
+            "h" => 3600.0,
+            _ => return Err(invalid()),
+        };
+        let count: f64 = count.trim().parse().map_err(|_| invalid())?;
+        if !count.is_finite() || count <= 0.0 {
+            return Err(invalid());
+        }
+
+        Ok(Rate(count / seconds))
+    }
+}
+
+/// the rate for one host, `HOST=RATE`, or every host without one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostRate {
+    pub host: Option<String>,
+    pub rate: Rate,
+}
+
+impl FromStr for HostRate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((host, rate)) if !host.trim().is_empty() => Ok(HostRate {
+                host: Some(host.trim().to_lowercase()),
+                rate: rate.parse()?,
+            }),
+            Some(_) => Err(Error::MissingRate(s.to_owned())),
+            None => Ok(HostRate {
+                host: None,
+                rate: s.parse()?,
+            }),
+        }
+    }
+}
+
+/// a token bucket holding up to a second of requests.
+///
+/// Requests reserve their token right away, taking the bucket into debt, and
+/// wait until it is paid back, so waiting requests are served in order.
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    fn new(rate: Rate) -> Self {
+        let capacity = rate.per_second().max(1.0);
+        TokenBucket {
+            rate: rate.per_second(),
+            capacity,
+            state: Mutex::new((capacity, Instant::now())),
+        }
+    }
+
+    /// take a token, returns how long to wait before using it.
+    fn reserve(&self, now: Instant) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let (ref mut tokens, ref mut last) = *state;
+
+        let elapsed = now.saturating_duration_since(*last).as_secs_f64();
+        *tokens = (*tokens + elapsed * self.rate).min(self.capacity) - 1.0;
+        *last = now.max(*last);
+
+        if *tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-*tokens / self.rate)
+        }
+    }
+}
+
+/// the rate limits of a run, shared by all sites.
+#[derive(Debug)]
+pub struct RateLimiter {
+    global: Option<TokenBucket>,
+    /// the rate of hosts without their own one.
+    host_rate: Option<Rate>,
+    host_rates: HashMap<String, Rate>,
+    hosts: Mutex<HashMap<String, Arc<TokenBucket>>>,
+}
+
+impl RateLimiter {
+    /// a limiter for the global rate and host rates, `None` without any.
+    pub fn new(rate: Option<Rate>, host_rates: &[HostRate]) -> Option<Self> {
+        if rate.is_none() && host_rates.is_empty() {
+            return None;
+        }
+
+        Some(RateLimiter {
+            global: rate.map(TokenBucket::new),
+            host_rate: host_rates
+                .iter()
+                .filter(|hr| hr.host.is_none())
+                .map(|hr| hr.rate)
+                .next_back(),
+            host_rates: host_rates
+                .iter()
+                .filter_map(|hr| Some((hr.host.clone()?, hr.rate)))
+                .collect(),
+            hosts: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// wait until a request to the URL is allowed, first by its host and
+    /// then by the global rate.
+    pub async fn acquire(&self, url: &Url) {
+        if let Some(bucket) = self.host_bucket(url) {
+            sleep(bucket.reserve(Instant::now())).await;
+        }
+        if let Some(ref bucket) = self.global {
+            sleep(bucket.reserve(Instant::now())).await;
+        }
+    }
+
+    fn host_bucket(&self, url: &Url) -> Option<Arc<TokenBucket>> {
+        let host = url.host_str()?;
+        let rate = self.host_rates.get(host).or(self.host_rate.as_ref())?;
+
+        let mut hosts = self.hosts.lock().unwrap();
+        Some(
+            hosts
+                .entry(host.to_owned())
+                .or_insert_with(|| Arc::new(TokenBucket::new(*rate)))
+                .clone(),
+        )
+    }
+}
+
+async fn sleep(duration: Duration) {
+    if !duration.is_zero() {
+        tokio::time::sleep(duration).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("50/s", 50.0)]
+    #[test_case("50", 50.0)]
+    #[test_case("120/m", 2.0)]
+    #[test_case("0.5/sec", 0.5)]
+    #[test_case("1800/h", 0.5)]
+    fn parse_rate(input: &str, expected: f64) {
+        assert_eq!(input.parse::<Rate>().unwrap().per_second(), expected);
+    }
+
+    #[test_case("")]
+    #[test_case("0/s")]
+    #[test_case("-1/s")]
+    #[test_case("50/d")]
+    #[test_case("fast")]
+    fn parse_rate_err(input: &str) {
+        assert!(input.parse::<Rate>().is_err());
+    }
+
+    #[test]
+    fn parse_host_rate() {
+        assert_eq!(
+            "Shop.Example.com=5/s".parse::<HostRate>().unwrap(),
+            HostRate {
+                host: Some("shop.example.com".to_owned()),
+                rate: Rate(5.0)
+            }
+        );
+        assert_eq!(
+            "10/s".parse::<HostRate>().unwrap(),
+            HostRate {
+                host: None,
+                rate: Rate(10.0)
+            }
+        );
+        assert!("=5/s".parse::<HostRate>().is_err());
+    }
+
+    #[test]
+    fn token_bucket() {
+        let bucket = TokenBucket::new(Rate(2.0));
+        let start = *bucket.state.lock().unwrap();
+        let at = |millis| start.1 + Duration::from_millis(millis);
+
+        // a full bucket allows a burst of a second of requests
+        assert_eq!(bucket.reserve(at(0)), Duration::ZERO);
+        assert_eq!(bucket.reserve(at(0)), Duration::ZERO);
+        // then they are spread out
+        assert_eq!(bucket.reserve(at(0)), Duration::from_millis(500));
+        assert_eq!(bucket.reserve(at(0)), Duration::from_millis(1000));
+        // paid back after waiting
+        assert_eq!(bucket.reserve(at(2500)), Duration::ZERO);
+    }
+
+    #[test]
+    fn limiter_hosts() {
+        let host_rates = ["1/s".parse().unwrap(), "b.example.com=5/s".parse().unwrap()];
+        let limiter = RateLimiter::new(None, &host_rates).unwrap();
+        let url = |s: &str| Url::parse(s).unwrap();
+
+        let a = limiter.host_bucket(&url("https://a.example.com/")).unwrap();
+        let b = limiter
+            .host_bucket(&url("https://b.example.com/x"))
+            .unwrap();
+        assert_eq!(a.rate, 1.0);
+        assert_eq!(b.rate, 5.0);
+        assert!(Arc::ptr_eq(
+            &a,
+            &limiter.host_bucket(&url("http://a.example.com/y")).unwrap()
+        ));
+
+        assert!(RateLimiter::new(None, &[]).is_none());
+    }
+
+    #[tokio::test]
+    async fn acquire() {
+        let limiter = RateLimiter::new(Some(Rate(20.0)), &[]).unwrap();
+        let url = Url::parse("https://www.example.com/").unwrap();
+
+        let start = Instant::now();
+        for _ in 0..30 {
+            limiter.acquire(&url).await;
+        }
+        // 20 from the full bucket, 10 more at 20/s
+        assert!(start.elapsed() >= Duration::from_millis(450));
+    }
+}
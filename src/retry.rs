@@ -0,0 +1,128 @@
+//! retrying failed warming requests with an exponential backoff.
+use crate::heater::ErrorKind;
+use chrono::{DateTime, Utc};
+use reqwest::{header::HeaderValue, StatusCode};
+use std::time::Duration;
+
+/// which failed requests are retried, and how long to wait in between.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// how often a request is retried, no retries with 0.
+    pub retries: usize,
+    pub statuses: Vec<StatusCode>,
+    pub errors: Vec<ErrorKind>,
+    /// the delay before the first retry, doubled for every further retry.
+    pub delay: Duration,
+    /// the longest delay, also for `Retry-After`.
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retries: 0,
+            statuses: vec![
+                StatusCode::TOO_MANY_REQUESTS,
+                StatusCode::BAD_GATEWAY,
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::GATEWAY_TIMEOUT,
+            ],
+            errors: vec![ErrorKind::Timeout, ErrorKind::Connect],
+            delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn retries_status(&self, status: StatusCode) -> bool {
+        self.statuses.contains(&status)
+    }
+
+    pub fn retries_error(&self, kind: ErrorKind) -> bool {
+        self.errors.contains(&kind)
+    }
+
+    /// the delay before the retry after `attempt` failed attempts.
+    ///
+    /// The `Retry-After` of the response replaces the exponential backoff,
+    /// which is jittered between half and the full delay so retries of
+    /// requests that failed together are spread out.
+    pub fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        if let Some(retry_after) = retry_after {
+            return retry_after.min(self.max_delay);
+        }
+
+        let backoff = self
+            .delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        backoff / 2 + backoff.mul_f64(rand::random::<f64>() / 2.0)
+    }
+}
+
+/// parse a `Retry-After` header, which has either the seconds to wait or the
+/// HTTP date to wait for.
+pub fn parse_retry_after(value: &HeaderValue, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.to_str().ok()?.trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    // dates in the past mean no waiting
+    Some(
+        (date.with_timezone(&Utc) - now)
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use test_case::test_case;
+
+    #[test]
+    fn backoff() {
+        let policy = RetryPolicy {
+            delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(5),
+            ..RetryPolicy::default()
+        };
+
+        for _ in 0..100 {
+            let first = policy.delay(0, None);
+            assert!(first >= Duration::from_millis(500) && first <= Duration::from_secs(1));
+
+            let third = policy.delay(2, None);
+            assert!(third >= Duration::from_secs(2) && third <= Duration::from_secs(4));
+
+            let capped = policy.delay(10, None);
+            assert!(capped >= Duration::from_millis(2500) && capped <= Duration::from_secs(5));
+        }
+
+        assert_eq!(
+            policy.delay(0, Some(Duration::from_secs(3))),
+            Duration::from_secs(3)
+        );
+        assert_eq!(
+            policy.delay(0, Some(Duration::from_secs(60))),
+            Duration::from_secs(5)
+        );
+    }
+
+    #[test_case("120", Some(Duration::from_secs(120)) ; "seconds")]
+    #[test_case("Wed, 21 Oct 2015 07:28:30 GMT", Some(Duration::from_secs(30)) ; "date")]
+    #[test_case("Wed, 21 Oct 2015 07:27:00 GMT", Some(Duration::ZERO) ; "date in the past")]
+    #[test_case("soon", None ; "invalid")]
+    fn retry_after(value: &str, expected: Option<Duration>) {
+        let now = Utc.with_ymd_and_hms(2015, 10, 21, 7, 28, 0).unwrap();
+        assert_eq!(
+            parse_retry_after(&HeaderValue::from_str(value).unwrap(), now),
+            expected
+        );
+    }
+}
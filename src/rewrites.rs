@@ -0,0 +1,240 @@
+//! rewriting of sitemap URLs, to warm another host than the one listed in
+//! the sitemap.
+use crate::config::Config;
+use reqwest::header::HeaderValue;
+use std::str::FromStr;
+use thiserror::Error;
+use url::Url;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("rewrite rule must look like `FROM => TO`")]
+    MissingSeparator,
+
+    #[error("invalid URL in rewrite rule")]
+    InvalidUrl(#[from] url::ParseError),
+
+    #[error("invalid host: {0}")]
+    InvalidHost(String),
+}
+
+/// replaces the `from` prefix of URLs with `to`, parsed from `FROM => TO`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrefixRewrite {
+    from: String,
+    to: String,
+}
+
+impl FromStr for PrefixRewrite {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (from, to) = s.split_once("=>").ok_or(Error::MissingSeparator)?;
+        let (from, to) = (from.trim(), to.trim());
+
+        // both sides have to be URLs, so the rewritten URLs are too.
+        Url::parse(from)?;
+        Url::parse(to)?;
+
+        Ok(PrefixRewrite {
+            from: from.to_owned(),
+            to: to.to_owned(),
+        })
+    }
+}
+
+/// the replacement host, with an optional port.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostRewrite {
+    host: String,
+    port: Option<u16>,
+}
+
+impl FromStr for HostRewrite {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidHost(s.to_owned());
+
+        let url = Url::parse(&format!("http://{s}")).map_err(|_| invalid())?;
+        if url.path() != "/" || url.query().is_some() || !url.username().is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(HostRewrite {
+            host: url.host_str().ok_or_else(invalid)?.to_owned(),
+            port: url.port(),
+        })
+    }
+}
+
+/// apply the configured rewrites to the URL.
+///
+/// The first matching prefix rule is applied, then the scheme and host are
+/// replaced.
+/// With `Config::keep_host_header`, the original `Host` header is returned
+/// when the host changed.
+pub fn rewrite(config: &Config, url: Url) -> (Url, Option<HeaderValue>) {
+    let original = authority(&url);
+    let mut url = url;
+
+    if let Some(rule) = config
+        .rewrite_prefixes
+        .iter()
+        .find(|rule| url.as_str().starts_with(&rule.from))
+    {
+        let rewritten = format!("{}{}", rule.to, &url.as_str()[rule.from.len()..]);
+        match Url::parse(&rewritten) {
+            Ok(rewritten) => url = rewritten,
+            Err(err) => log::warn!("could not rewrite {url} to {rewritten}: {err:?}"),
+        }
+    }
+
+    if let Some(ref scheme) = config.rewrite_scheme
+        && url.set_scheme(scheme).is_err()
+    {
+        log::warn!("could not change the scheme of {url} to {scheme}");
+    }
+
+    if let Some(ref host) = config.rewrite_host {
+        if let Err(err) = url.set_host(Some(&host.host)) {
+            log::warn!(
+                "could not change the host of {url} to {}: {err:?}",
+                host.host
+            );
+        } else if url.set_port(host.port).is_err() {
+            log::warn!("could not change the port of {url}");
+        }
+    }
+
+    let host = match original {
+        Some(original)
+            if config.keep_host_header && authority(&url).as_ref() != Some(&original) =>
+        {
+            HeaderValue::from_str(&original).ok()
+        }
+        _ => None,
+    };
+
+    (url, host)
+}
+
+/// the `Host` header value for the URL.
+fn authority(url: &Url) -> Option<String> {
+    let host = url.host_str()?;
+    Some(match url.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_owned(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn apply(config: &Config, url: &str) -> (String, Option<HeaderValue>) {
+        let (url, host) = rewrite(config, Url::parse(url).unwrap());
+        (url.to_string(), host)
+    }
+
+    #[test_case("https://www.example.com/ => https://cdn-edge.example.net/")]
+    #[test_case("https://www.example.com/=>https://cdn-edge.example.net/"; "without spaces")]
+    fn parse_prefix_rewrite(rule: &str) {
+        assert_eq!(
+            rule.parse::<PrefixRewrite>().unwrap(),
+            PrefixRewrite {
+                from: "https://www.example.com/".into(),
+                to: "https://cdn-edge.example.net/".into(),
+            }
+        );
+    }
+
+    #[test_case("https://www.example.com/"; "missing separator")]
+    #[test_case("/docs/ => /other/"; "not an URL")]
+    fn invalid_prefix_rewrite(rule: &str) {
+        assert!(rule.parse::<PrefixRewrite>().is_err());
+    }
+
+    #[test_case("staging.example.com", "staging.example.com", None)]
+    #[test_case("localhost:8080", "localhost", Some(8080))]
+    fn parse_host_rewrite(input: &str, host: &str, port: Option<u16>) {
+        assert_eq!(
+            input.parse::<HostRewrite>().unwrap(),
+            HostRewrite {
+                host: host.into(),
+                port,
+            }
+        );
+    }
+
+    #[test_case("staging.example.com/path")]
+    #[test_case("user@staging.example.com")]
+    #[test_case("")]
+    fn invalid_host_rewrite(input: &str) {
+        assert!(input.parse::<HostRewrite>().is_err());
+    }
+
+    #[test]
+    fn no_rewrites() {
+        assert_eq!(
+            apply(&Config::new(), "https://www.example.com/page?a=1"),
+            ("https://www.example.com/page?a=1".into(), None)
+        );
+    }
+
+    #[test]
+    fn rewrite_host_and_scheme() {
+        let mut config = Config::new();
+        config.rewrite_host = Some("localhost:8080".parse().unwrap());
+        config.rewrite_scheme = Some("http".into());
+
+        assert_eq!(
+            apply(&config, "https://www.example.com/page?a=1"),
+            ("http://localhost:8080/page?a=1".into(), None)
+        );
+    }
+
+    #[test]
+    fn rewrite_prefix() {
+        let mut config = Config::new();
+        config.rewrite_prefixes = vec![
+            "https://www.example.com/de/ => https://de.example.net/"
+                .parse()
+                .unwrap(),
+            "https://www.example.com/ => https://cdn-edge.example.net/www/"
+                .parse()
+                .unwrap(),
+        ];
+
+        assert_eq!(
+            apply(&config, "https://www.example.com/de/page").0,
+            "https://de.example.net/page"
+        );
+        assert_eq!(
+            apply(&config, "https://www.example.com/en/page").0,
+            "https://cdn-edge.example.net/www/en/page"
+        );
+        assert_eq!(
+            apply(&config, "https://other.example.com/en/page").0,
+            "https://other.example.com/en/page"
+        );
+    }
+
+    #[test]
+    fn keep_host_header() {
+        let mut config = Config::new();
+        config.rewrite_host = Some("cdn-edge.example.net".parse().unwrap());
+        config.keep_host_header = true;
+
+        assert_eq!(
+            apply(&config, "https://www.example.com:8443/page"),
+            (
+                "https://cdn-edge.example.net/page".into(),
+                Some(HeaderValue::from_static("www.example.com:8443"))
+            )
+        );
+        // the host header is only needed when the host changed
+        assert_eq!(apply(&config, "https://cdn-edge.example.net/page").1, None);
+    }
+}
@@ -0,0 +1,152 @@
+use crate::{client, config::Config, sitemaps::SiteMapError};
+use url::Url;
+
+/// find the sitemaps of a site via the `Sitemap:` directives in its `robots.txt`.
+///
+/// Falls back to `/sitemap.xml` when `robots.txt` is missing or doesn't list
+/// any sitemaps.
+pub async fn discover_sitemaps(config: &Config, site: &Url) -> Result<Vec<Url>, SiteMapError> {
+    let robots_url = site.join("/robots.txt").expect("invalid robots.txt url");
+
+    log::info!("fetching {robots_url}");
+    let response = client::build_for_sitemaps(config)
+        .get(robots_url)
+        .send()
+        .await?;
+
+    let sitemaps = if response.status().is_success() {
+        let base = response.url().clone();
+        parse_sitemaps(&base, &response.text().await?)
+    } else {
+        log::warn!("could not fetch robots.txt, status {}", response.status());
+        Vec::new()
+    };
+
+    if sitemaps.is_empty() {
+        log::info!("no sitemaps found in robots.txt, falling back to /sitemap.xml");
+        Ok(vec![site
+            .join("/sitemap.xml")
+            .expect("invalid sitemap url")])
+    } else {
+        Ok(sitemaps)
+    }
+}
+
+fn parse_sitemaps(base: &Url, text: &str) -> Vec<Url> {
+    let mut result: Vec<Url> = Vec::new();
+
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap_or("");
+
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+
+        if !key.trim().eq_ignore_ascii_case("sitemap") {
+            continue;
+        }
+
+        match base.join(value.trim()) {
+            Ok(url) => {
+                if !result.contains(&url) {
+                    result.push(url);
+                }
+            }
+            Err(err) => log::warn!("could not parse sitemap url in robots.txt: {err:?}"),
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base() -> Url {
+        Url::parse("https://www.example.com/robots.txt").unwrap()
+    }
+
+    #[test]
+    fn parse_empty() {
+        assert!(parse_sitemaps(&base(), "").is_empty());
+    }
+
+    #[test]
+    fn parse_multiple() {
+        let text = "
+            User-agent: *
+            Disallow: /admin # no admin
+            Sitemap: https://www.example.com/sitemap.xml
+            sitemap:https://www.example.com/news.xml.gz
+            # Sitemap: https://www.example.com/commented.xml
+            SITEMAP: /relative.xml
+            Sitemap: https://www.example.com/sitemap.xml
+        ";
+
+        assert_eq!(
+            parse_sitemaps(&base(), text)[..],
+            [
+                Url::parse("https://www.example.com/sitemap.xml").unwrap(),
+                Url::parse("https://www.example.com/news.xml.gz").unwrap(),
+                Url::parse("https://www.example.com/relative.xml").unwrap(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn discover_from_robots() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/robots.txt")
+            .with_status(200)
+            .with_body(format!(
+                "User-agent: *\nSitemap: {0}/one.xml\nSitemap: {0}/two.xml\n",
+                server.url()
+            ))
+            .create_async()
+            .await;
+
+        let site = Url::parse(&server.url()).unwrap();
+        assert_eq!(
+            discover_sitemaps(&Config::new(), &site).await.unwrap()[..],
+            [
+                site.join("/one.xml").unwrap(),
+                site.join("/two.xml").unwrap()
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn fallback_without_robots() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/robots.txt")
+            .with_status(404)
+            .create_async()
+            .await;
+
+        let site = Url::parse(&server.url()).unwrap();
+        assert_eq!(
+            discover_sitemaps(&Config::new(), &site).await.unwrap()[..],
+            [site.join("/sitemap.xml").unwrap()]
+        );
+    }
+
+    #[tokio::test]
+    async fn fallback_without_sitemap_directive() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/robots.txt")
+            .with_status(200)
+            .with_body("User-agent: *\nDisallow:\n")
+            .create_async()
+            .await;
+
+        let site = Url::parse(&server.url()).unwrap();
+        assert_eq!(
+            discover_sitemaps(&Config::new(), &site).await.unwrap()[..],
+            [site.join("/sitemap.xml").unwrap()]
+        );
+    }
+}
@@ -0,0 +1,643 @@
+//! warming a single site, from discovering its URLs to warming the pages,
+//! media and assets.
+use crate::{
+    config::{self, Config},
+    crawl, filters, heater, rewrites, robots,
+    sitemaps::{self, Entry, SiteMapError, SkippedSitemap, SkippedSitemaps},
+    status, targets,
+};
+use chrono::Utc;
+use futures::{
+    future,
+    stream::{self, BoxStream},
+    StreamExt,
+};
+use log::info;
+use std::{
+    collections::HashSet,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use url::Url;
+
+/// a site with its sources and configuration.
+#[derive(Debug, Clone)]
+pub struct Site {
+    /// the name in the summary, when warming several sites.
+    pub name: Option<String>,
+    /// sitemap URLs or local files, site roots, `-` for stdin, or the start
+    /// pages with `Config::crawl`.
+    pub sources: Vec<String>,
+    pub config: Config,
+}
+
+/// the pages and media a site would be warmed with.
+#[derive(Debug)]
+pub struct Plan {
+    pub pages: Vec<targets::Target>,
+    pub media: Vec<targets::Target>,
+    /// the number of distinct URLs skipped by the filters.
+    pub filtered_count: usize,
+    pub skipped_sitemaps: Vec<SkippedSitemap>,
+    /// the error that stopped the discovery.
+    pub sitemap_error: Option<SiteMapError>,
+}
+
+/// the outcome of warming a site.
+#[derive(Debug)]
+pub struct SiteResults {
+    pub pages: heater::Results,
+    pub media: Option<heater::Results>,
+    pub assets: Option<heater::Results>,
+    /// the number of distinct URLs skipped by the filters.
+    pub filtered_count: usize,
+    pub skipped_sitemaps: Vec<SkippedSitemap>,
+    /// the error that stopped the discovery, the URLs found until then were
+    /// still warmed.
+    pub sitemap_error: Option<SiteMapError>,
+    /// whether the discovery or warming stopped at `Config::deadline`.
+    pub deadline_reached: bool,
+}
+
+/// discover and warm the URLs of the site.
+///
+/// Fails when no URLs could be found because of an error, or when the
+/// discovery failed while ordering or sampling.
+pub async fn warm(site: &Site) -> Result<SiteResults, SiteMapError> {
+    let result = warm_site(site).await;
+    if result.is_err() {
+        status::finish_discovery();
+    }
+    result
+}
+
+async fn warm_site(site: &Site) -> Result<SiteResults, SiteMapError> {
+    let config = &site.config;
+    let skipped_sitemaps = SkippedSitemaps::default();
+
+    // the discovery ends at the deadline too, also while reading robots.txt
+    let discovery_stopped = AtomicBool::new(false);
+    let sources = match config.deadline {
+        Some(deadline) => {
+            match tokio::time::timeout_at(deadline.into(), sources(site, &skipped_sitemaps)).await {
+                Ok(sources) => sources?,
+                Err(_) => {
+                    discovery_stopped.store(true, Ordering::Relaxed);
+                    Vec::new()
+                }
+            }
+        }
+        None => sources(site, &skipped_sitemaps).await?,
+    };
+
+    let mut sitemap_error: Option<SiteMapError> = None;
+    let mut filtered: HashSet<Url> = HashSet::new();
+    let entries = entries(config, sources, &mut sitemap_error, &mut filtered);
+    let entries = match config.deadline {
+        Some(deadline) => entries
+            .take_until({
+                let stopped = &discovery_stopped;
+                async move {
+                    tokio::time::sleep_until(deadline.into()).await;
+                    stopped.store(true, Ordering::Relaxed);
+                }
+            })
+            .boxed(),
+        None => entries,
+    };
+
+    // ordering and sampling need all the entries, otherwise we start warming
+    // right away.
+    let entries = if config.order == config::Order::Sitemap && config.sample.is_none() {
+        entries
+    } else {
+        let entries: Vec<Entry> = entries.collect().await;
+        if let Some(err) = sitemap_error.take() {
+            return Err(err);
+        }
+        let mut entries = filters::sample(config, entries);
+        filters::sort(config, &mut entries);
+
+        stream::iter(entries).boxed()
+    };
+
+    let media_config = config.for_media();
+    let default_variations = config.possible_variations();
+    let media_variations = media_config.possible_variations();
+
+    let mut builder = targets::TargetBuilder::default();
+    let mut page_count: usize = 0;
+
+    let pages = entries
+        .flat_map(|entry| {
+            let media_before = builder.media.len();
+            let pages = builder.add(config, entry);
+
+            status::add_length(
+                pages
+                    .iter()
+                    .map(|t| match t.languages {
+                        Some(ref languages) => config.possible_variations_for(Some(languages)),
+                        None => default_variations,
+                    })
+                    .sum::<u64>()
+                    + (builder.media.len() - media_before) as u64 * media_variations,
+            );
+            page_count += pages.len();
+
+            stream::iter(pages)
+        })
+        // runs when the discovery is done
+        .chain(
+            stream::once(async { status::finish_discovery() }).filter_map(|_| future::ready(None)),
+        );
+
+    info!("running heater...");
+    let results = heater::heat_stream(config, pages).await;
+
+    let media = std::mem::take(&mut builder.media);
+    info!(
+        "... warmed {} URLs, found {} media URLs",
+        page_count,
+        media.len()
+    );
+
+    // no more passes after the deadline
+    let mut deadline_reached = discovery_stopped.into_inner() || results.deadline_reached;
+
+    let media_results = if media.is_empty() || deadline_reached {
+        None
+    } else {
+        info!("warming media...");
+        let results = heater::heat(&media_config, media.into_iter()).await;
+        deadline_reached |= results.deadline_reached;
+        Some(results)
+    };
+
+    // assets that were already warmed as page or media are skipped
+    let mut seen_assets: HashSet<Url> = HashSet::new();
+    let assets: Vec<targets::Target> = results
+        .assets
+        .iter()
+        .map(|url| {
+            let (url, host) = rewrites::rewrite(config, url.clone());
+            targets::Target {
+                host,
+                ..targets::Target::from(url)
+            }
+        })
+        .filter(|target| !builder.contains(&target.url) && seen_assets.insert(target.url.clone()))
+        .collect();
+
+    let asset_results = if assets.is_empty() || deadline_reached {
+        None
+    } else {
+        info!("warming {} assets...", assets.len());
+        let asset_config = config.for_assets();
+        status::add_length(assets.len() as u64 * asset_config.possible_variations());
+        let results = heater::heat(&asset_config, assets.into_iter()).await;
+        deadline_reached |= results.deadline_reached;
+        Some(results)
+    };
+
+    // nothing to summarize when the sitemap couldn't be loaded at all
+    if page_count == 0
+        && let Some(err) = sitemap_error.take()
+    {
+        return Err(err);
+    }
+
+    Ok(SiteResults {
+        pages: results,
+        media: media_results,
+        assets: asset_results,
+        filtered_count: builder.filtered_count(&filtered),
+        skipped_sitemaps: skipped_sitemaps.take(),
+        sitemap_error,
+        deadline_reached,
+    })
+}
+
+/// list the pages and media the site would be warmed with, without warming
+/// them. The sitemaps are still fetched, and the pages when crawling.
+///
+/// Assets are only found while warming, so they are not part of the plan.
+pub async fn plan(site: &Site) -> Result<Plan, SiteMapError> {
+    let config = &site.config;
+    let skipped_sitemaps = SkippedSitemaps::default();
+    let sources = sources(site, &skipped_sitemaps).await?;
+
+    let mut sitemap_error: Option<SiteMapError> = None;
+    let mut filtered: HashSet<Url> = HashSet::new();
+    let entries: Vec<Entry> = entries(config, sources, &mut sitemap_error, &mut filtered)
+        .collect()
+        .await;
+
+    // like warming, an error while ordering or sampling fails the site
+    let is_ordered = config.order != config::Order::Sitemap || config.sample.is_some();
+    if let Some(err) = sitemap_error.take_if(|_| entries.is_empty() || is_ordered) {
+        return Err(err);
+    }
+
+    let mut entries = filters::sample(config, entries);
+    filters::sort(config, &mut entries);
+
+    let mut builder = targets::TargetBuilder::default();
+    let pages: Vec<targets::Target> = entries
+        .into_iter()
+        .flat_map(|entry| builder.add(config, entry))
+        .collect();
+
+    Ok(Plan {
+        pages,
+        filtered_count: builder.filtered_count(&filtered),
+        media: builder.media,
+        skipped_sitemaps: skipped_sitemaps.take(),
+        sitemap_error,
+    })
+}
+
+/// the entries of all sources of the site.
+async fn sources(
+    site: &Site,
+    skipped: &SkippedSitemaps,
+) -> Result<Vec<BoxStream<'static, Result<Entry, SiteMapError>>>, SiteMapError> {
+    // the sitemaps of all sources share the URL limit and the visited sitemaps
+    let loader = sitemaps::Loader::new(&site.config, skipped);
+    let mut sources = Vec::new();
+    for source in &site.sources {
+        sources.push(source_entries(&site.config, source, &loader).await?);
+    }
+    Ok(sources)
+}
+
+/// the entries of the sources that pass the filters, the URLs of the others
+/// are collected in `filtered`.
+///
+/// The first sitemap error stops the discovery and is stored in `error`, but
+/// the URLs found until then are kept.
+fn entries<'a>(
+    config: &'a Config,
+    sources: Vec<BoxStream<'static, Result<Entry, SiteMapError>>>,
+    error: &'a mut Option<SiteMapError>,
+    filtered: &'a mut HashSet<Url>,
+) -> BoxStream<'a, Entry> {
+    let now = Utc::now();
+    stream::iter(sources)
+        .flatten()
+        .scan(error, |error, result| {
+            future::ready(match result {
+                Ok(entry) => Some(entry),
+                Err(err) => {
+                    **error = Some(err);
+                    None
+                }
+            })
+        })
+        .filter(move |entry| {
+            let is_match = filters::is_match(config, entry, now);
+            if !is_match {
+                filtered.insert(entry.url.clone());
+            }
+            future::ready(is_match)
+        })
+        .boxed()
+}
+
+/// the entries of a single source of the site.
+async fn source_entries(
+    config: &Config,
+    source: &str,
+    loader: &sitemaps::Loader,
+) -> Result<BoxStream<'static, Result<Entry, SiteMapError>>, SiteMapError> {
+    if source == "-" {
+        info!("reading URLs from stdin");
+        return Ok(stream::iter(sitemaps::from_stdin()?.into_iter().map(Ok)).boxed());
+    }
+
+    if config.crawl {
+        let seed = Url::parse(source)?;
+        info!("crawling from {seed}");
+        return Ok(crawl::stream(config, seed).map(Ok).boxed());
+    }
+
+    let sitemap_url = sitemaps::parse_location(source)?;
+
+    // a bare site root can't be a sitemap, so we look into its robots.txt
+    let is_site_root = sitemap_url.scheme() != "file" && sitemap_url.path() == "/";
+    // only sources given by the user may be local files, not what robots.txt lists
+    let (sitemap_urls, allow_local) = if config.robots || is_site_root {
+        (
+            robots::discover_sitemaps(config, &sitemap_url).await?,
+            false,
+        )
+    } else {
+        (vec![sitemap_url], true)
+    };
+
+    let loader = loader.clone();
+    Ok(stream::iter(sitemap_urls)
+        .flat_map(move |sitemap_url| {
+            info!("fetching sitemap from {sitemap_url}");
+            loader.stream(sitemap_url, allow_local)
+        })
+        .boxed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn warm_multiple_sources() {
+        let mut server = mockito::Server::new_async().await;
+        let url = server.url();
+
+        let mut sources = Vec::new();
+        for name in ["a", "b"] {
+            let _ = server
+                .mock("GET", format!("/{name}.txt").as_str())
+                .with_status(200)
+                .with_header("content-type", "text/plain")
+                .with_body(format!("{url}/{name}/1\n{url}/{name}/2\n"))
+                .create_async()
+                .await;
+            sources.push(format!("{url}/{name}.txt"));
+        }
+        let pages = server
+            .mock("GET", mockito::Matcher::Regex("^/[ab]/".into()))
+            .with_status(200)
+            .expect(4)
+            .create_async()
+            .await;
+
+        let site = Site {
+            name: None,
+            sources,
+            config: Config::new(),
+        };
+        let results = warm(&site).await.unwrap();
+
+        pages.assert_async().await;
+        assert_eq!(
+            results.pages.statuses.get(&reqwest::StatusCode::OK),
+            Some(&4)
+        );
+        assert!(results.sitemap_error.is_none());
+    }
+
+    #[tokio::test]
+    async fn robots_sitemaps_share_limits() {
+        let mut server = mockito::Server::new_async().await;
+        let url = server.url();
+
+        let mut mock = |path: &str, content_type: &str, body: String| {
+            server
+                .mock("GET", path)
+                .with_status(200)
+                .with_header("content-type", content_type)
+                .with_body(body)
+        };
+        let _robots = mock(
+            "/robots.txt",
+            "text/plain",
+            format!("Sitemap: {url}/a.xml\nSitemap: {url}/b.xml\n"),
+        )
+        .create_async()
+        .await;
+        let mut indexes = Vec::new();
+        for name in ["a", "b"] {
+            let index = mock(
+                &format!("/{name}.xml"),
+                "text/xml",
+                format!(
+                    r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+                        <sitemap><loc>{url}/shared.txt</loc></sitemap>
+                        <sitemap><loc>{url}/{name}.txt</loc></sitemap>
+                    </sitemapindex>"#
+                ),
+            );
+            indexes.push(index.create_async().await);
+            let pages = mock(
+                &format!("/{name}.txt"),
+                "text/plain",
+                format!("{url}/{name}/1\n{url}/{name}/2\n"),
+            );
+            indexes.push(pages.create_async().await);
+        }
+        let shared = mock("/shared.txt", "text/plain", format!("{url}/shared\n"))
+            .expect(1)
+            .create_async()
+            .await;
+        let _pages = server
+            .mock("GET", mockito::Matcher::Regex("^/(a|shared)/?".into()))
+            .with_status(200)
+            .create_async()
+            .await;
+
+        // 3 URLs from the first sitemap, the second one exceeds the limit
+        let mut config = Config::new();
+        config.max_sitemap_urls = 3;
+        let site = Site {
+            name: None,
+            sources: vec![format!("{url}/")],
+            config,
+        };
+        let results = warm(&site).await.unwrap();
+
+        shared.assert_async().await;
+        assert_eq!(
+            results.pages.statuses.get(&reqwest::StatusCode::OK),
+            Some(&3)
+        );
+        assert!(matches!(
+            results.sitemap_error,
+            Some(SiteMapError::TooManyUrls(3))
+        ));
+    }
+
+    #[tokio::test]
+    async fn warm_deadline() {
+        let mut server = mockito::Server::new_async().await;
+        let sitemap = server
+            .mock("GET", "/sitemap.txt")
+            .with_status(200)
+            .with_header("content-type", "text/plain")
+            .with_body(format!("{}/page\n", server.url()))
+            .expect(1)
+            .create_async()
+            .await;
+        let page = server
+            .mock("GET", "/page")
+            .with_status(200)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let mut site = Site {
+            name: None,
+            sources: vec![format!("{}/sitemap.txt", server.url())],
+            config: Config::new(),
+        };
+        site.config.deadline = Some(std::time::Instant::now() + std::time::Duration::from_secs(60));
+        let results = warm(&site).await.unwrap();
+        assert!(!results.deadline_reached);
+
+        // neither discovered nor warmed after the deadline
+        site.config.deadline = Some(std::time::Instant::now());
+        let results = warm(&site).await.unwrap();
+        assert!(results.deadline_reached);
+        assert!(results.pages.statuses.is_empty());
+
+        sitemap.assert_async().await;
+        page.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn warm_crawled_once() {
+        let mut server = mockito::Server::new_async().await;
+        let mut pages = Vec::new();
+        for (path, body) in [
+            ("/", r#"<a href="/a">a</a> <a href="/b">b</a>"#),
+            ("/a", r#"<a href="/b">b</a> <a href="/">home</a>"#),
+            ("/b", r#"<a href="/a">a</a>"#),
+        ] {
+            pages.push(
+                server
+                    .mock("GET", path)
+                    .with_status(200)
+                    .with_header("content-type", "text/html")
+                    .with_header("x-cache", "MISS")
+                    .with_body(body)
+                    .expect(1)
+                    .create_async()
+                    .await,
+            );
+        }
+
+        let mut site = Site {
+            name: None,
+            sources: vec![format!("{}/", server.url())],
+            config: Config::new(),
+        };
+        site.config.crawl = true;
+        let results = warm(&site).await.unwrap();
+
+        // the crawler's responses are the warming results
+        assert_eq!(
+            results.pages.statuses.get(&reqwest::StatusCode::OK),
+            Some(&3)
+        );
+        assert_eq!(results.pages.cache_hits.get(&Some(false)), Some(&3));
+        for page in pages {
+            page.assert_async().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn plan_filtered_distinct() {
+        let mut server = mockito::Server::new_async().await;
+        let url = server.url();
+        let urls: String = (1..=5)
+            .map(|i| format!("{url}/x/{i}\n"))
+            .chain([format!("{url}/y\n")])
+            .collect();
+        for name in ["a", "b"] {
+            let _ = server
+                .mock("GET", format!("/{name}.txt").as_str())
+                .with_status(200)
+                .with_header("content-type", "text/plain")
+                .with_body(&urls)
+                .create_async()
+                .await;
+        }
+
+        // the same URLs from a duplicated source and another sitemap
+        let mut config = Config::new();
+        config.exclude = vec!["/x/*".parse().unwrap()];
+        let site = Site {
+            name: None,
+            sources: ["a", "b", "a"]
+                .map(|name| format!("{url}/{name}.txt"))
+                .to_vec(),
+            config,
+        };
+        let plan = plan(&site).await.unwrap();
+
+        assert_eq!(plan.pages.len(), 1);
+        assert_eq!(plan.filtered_count, 5);
+    }
+
+    #[tokio::test]
+    async fn plan_sampled_media() {
+        let mut server = mockito::Server::new_async().await;
+        let urls: String = (0..5)
+            .map(|i| {
+                format!(
+                    "<url>
+                        <loc>https://www.example.com/{i}</loc>
+                        <image:image><image:loc>https://www.example.com/{i}.jpg</image:loc></image:image>
+                        <image:image><image:loc>https://cdn.example.com/{i}.jpg</image:loc></image:image>
+                    </url>"
+                )
+            })
+            .collect();
+        let _ = server
+            .mock("GET", "/sitemap.xml")
+            .with_status(200)
+            .with_header("content-type", "text/xml")
+            .with_body(format!(
+                r#"<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9"
+                        xmlns:image="http://www.google.com/schemas/sitemap-image/1.1">{urls}</urlset>"#
+            ))
+            .create_async()
+            .await;
+
+        let mut config = Config::new();
+        config.include_media = true;
+        config.exclude = vec!["host:cdn.example.com".parse().unwrap()];
+        config.sample = Some(config::Sample::Count(2));
+        config.sample_seed = Some(1);
+        let site = Site {
+            name: None,
+            sources: vec![format!("{}/sitemap.xml", server.url())],
+            config,
+        };
+        let plan = plan(&site).await.unwrap();
+
+        // only the media of the sampled pages, without the excluded host
+        assert_eq!(plan.pages.len(), 2);
+        let media: Vec<String> = plan
+            .media
+            .iter()
+            .map(|target| target.url.to_string())
+            .collect();
+        let expected: Vec<String> = plan
+            .pages
+            .iter()
+            .map(|target| format!("{}.jpg", target.url))
+            .collect();
+        assert_eq!(media, expected);
+        assert_eq!(plan.filtered_count, 2);
+    }
+
+    #[tokio::test]
+    async fn warm_missing_sitemap() {
+        let mut server = mockito::Server::new_async().await;
+        let _ = server
+            .mock("GET", "/sitemap.xml")
+            .with_status(404)
+            .create_async()
+            .await;
+
+        let site = Site {
+            name: None,
+            sources: vec![format!("{}/sitemap.xml", server.url())],
+            config: Config::new(),
+        };
+
+        assert!(matches!(
+            warm(&site).await,
+            Err(SiteMapError::HttpStatus { .. })
+        ));
+    }
+}
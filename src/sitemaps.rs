@@ -1,11 +1,106 @@
-use async_recursion::async_recursion;
-use reqwest::{Client, IntoUrl};
+pub use crate::extensions::Alternate;
+use crate::{
+    cache::{Cached, SitemapCache},
+    client,
+    config::Config,
+    extensions, feeds,
+    heater::Warmed,
+};
+use chrono::{DateTime, FixedOffset};
+use flate2::read::GzDecoder;
+use futures::{
+    future,
+    stream::{self, BoxStream},
+    StreamExt,
+};
+use reqwest::{header, Client, StatusCode};
+pub use sitemap::structs::ChangeFreq;
 use sitemap::{
     reader::{SiteMapEntity, SiteMapReader},
-    structs::Location,
+    structs::{LastMod, Location, Priority, UrlEntry},
+};
+use std::{
+    collections::HashSet,
+    io::Read,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
 };
 use thiserror::Error;
 use url::Url;
+use xml::reader::{EventReader, XmlEvent};
+
+const GZIP_MAGIC_BYTES: [u8; 2] = [0x1f, 0x8b];
+
+/// delay before the first retry of a sitemap, doubled for every further retry.
+const RETRY_DELAY: Duration = Duration::from_millis(250);
+
+/// the kinds of documents we can extract URLs from.
+#[derive(Debug, PartialEq, Eq)]
+enum Format {
+    /// XML sitemap, either `urlset` or `sitemapindex`
+    Xml,
+    /// plain text sitemap with one URL per line
+    Text,
+    /// RSS or Atom feed
+    Feed,
+}
+
+/// a page URL with the metadata the sitemap provides for it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub url: Url,
+    pub lastmod: Option<DateTime<FixedOffset>>,
+    pub changefreq: Option<ChangeFreq>,
+    pub priority: Option<f32>,
+    pub alternates: Vec<Alternate>,
+    pub media: Vec<Url>,
+    /// the sitemap the entry was listed in
+    pub sitemap: Option<Url>,
+    /// the result of requesting the page while crawling
+    pub warmed: Option<Warmed>,
+}
+
+impl From<Url> for Entry {
+    fn from(url: Url) -> Self {
+        Entry {
+            url,
+            lastmod: None,
+            changefreq: None,
+            priority: None,
+            alternates: Vec::new(),
+            media: Vec::new(),
+            sitemap: None,
+            warmed: None,
+        }
+    }
+}
+
+impl Entry {
+    fn from_url_entry(url: Url, url_entry: UrlEntry) -> Self {
+        Entry {
+            url,
+            lastmod: match url_entry.lastmod {
+                LastMod::DateTime(dt) => Some(dt),
+                _ => None,
+            },
+            changefreq: match url_entry.changefreq {
+                ChangeFreq::None | ChangeFreq::ParseErr(_) => None,
+                changefreq => Some(changefreq),
+            },
+            priority: match url_entry.priority {
+                Priority::Value(value) => Some(value),
+                _ => None,
+            },
+            alternates: Vec::new(),
+            media: Vec::new(),
+            sitemap: None,
+            warmed: None,
+        }
+    }
+}
 
 #[derive(Error, Debug)]
 pub enum SiteMapError {
@@ -14,52 +109,601 @@ pub enum SiteMapError {
 
     #[error("XML parsing error")]
     XmlError(#[from] xml::reader::Error),
+
+    #[error("could not decompress gzipped sitemap")]
+    Decompression(#[source] std::io::Error),
+
+    #[error("invalid sitemap URL")]
+    InvalidUrl(#[from] url::ParseError),
+
+    #[error("invalid sitemap path: {0}")]
+    InvalidPath(String),
+
+    #[error("could not read sitemap file")]
+    File(#[source] std::io::Error),
+
+    #[error(
+        "sitemap {0} is not allowed, only http(s) sitemaps or local files given as source are"
+    )]
+    NotAllowed(Url),
+
+    #[error("sitemap {0} references itself")]
+    Cycle(Url),
+
+    #[error("sitemap {url} is nested deeper than {max_depth} levels")]
+    TooDeep { url: Url, max_depth: usize },
+
+    #[error("sitemaps contain more than {0} URLs")]
+    TooManyUrls(usize),
+
+    #[error("sitemap {url} is larger than {max_size} bytes")]
+    TooLarge { url: Url, max_size: usize },
+
+    #[error("sitemap {url} returned HTTP status {status}")]
+    HttpStatus { url: Url, status: StatusCode },
+}
+
+impl SiteMapError {
+    /// errors that might go away when the sitemap is fetched again.
+    fn is_transient(&self) -> bool {
+        match self {
+            SiteMapError::RequestError(err) => err.is_timeout() || err.is_connect(),
+            SiteMapError::HttpStatus { status, .. } => {
+                status.is_server_error()
+                    || *status == StatusCode::TOO_MANY_REQUESTS
+                    || *status == StatusCode::REQUEST_TIMEOUT
+            }
+            _ => false,
+        }
+    }
 }
 
-pub async fn get<T: IntoUrl + Send>(url: T) -> Result<Vec<Url>, SiteMapError> {
-    get_inner(Client::new(), url).await
+/// a child sitemap that was skipped because it could not be loaded.
+#[derive(Debug)]
+pub struct SkippedSitemap {
+    pub url: Url,
+    pub error: SiteMapError,
 }
 
-#[async_recursion]
-async fn get_inner<T>(client: Client, url: T) -> Result<Vec<Url>, SiteMapError>
-where
-    T: IntoUrl + Send,
-{
-    let mut result: Vec<Url> = Vec::new();
+/// collects the child sitemaps skipped with `Config::skip_broken_sitemaps`,
+/// shared between the sitemap streams and the summary.
+#[derive(Debug, Default, Clone)]
+pub struct SkippedSitemaps(Arc<Mutex<Vec<SkippedSitemap>>>);
 
-    let response = client.get(url).send().await?;
+impl SkippedSitemaps {
+    fn push(&self, url: Url, error: SiteMapError) {
+        self.0.lock().unwrap().push(SkippedSitemap { url, error });
+    }
 
-    let text = response.text().await?;
-    let parser = SiteMapReader::new(text.as_bytes());
-    for entity in parser {
-        match entity {
-            SiteMapEntity::Url(url_entry) => match url_entry.loc {
-                Location::None => {}
-                Location::Url(url) => result.push(url),
-                Location::ParseErr(err) => log::warn!("could not parse entry url: {err:?}"),
-            },
-            SiteMapEntity::SiteMap(sitemap_entry) => match sitemap_entry.loc {
-                Location::None => {}
-                Location::Url(url) => {
-                    let mut urls = get_inner(client.clone(), url).await?;
-                    result.append(&mut urls);
+    /// removes and returns the skipped sitemaps collected so far.
+    pub fn take(&self) -> Vec<SkippedSitemap> {
+        std::mem::take(&mut self.0.lock().unwrap())
+    }
+}
+
+/// loads the sitemaps of a site. The URL limit and the sitemaps that were
+/// already loaded are shared by all sitemaps of the site, from all its sources
+/// and its robots.txt.
+#[derive(Clone)]
+pub struct Loader(Arc<Context>);
+
+impl Loader {
+    pub fn new(config: &Config, skipped: &SkippedSitemaps) -> Self {
+        Loader(Arc::new(Context {
+            client: client::build_for_sitemaps(config),
+            concurrency: config.concurrent_sitemap_requests.max(1),
+            visited: Mutex::new(HashSet::new()),
+            url_count: AtomicUsize::new(0),
+            max_depth: config.max_sitemap_depth,
+            max_urls: config.max_sitemap_urls,
+            max_size: config.max_sitemap_size,
+            parse_extensions: config.hreflang || config.include_media,
+            retries: config.sitemap_retries,
+            skip_broken: config.skip_broken_sitemaps,
+            skipped: skipped.clone(),
+            cache: config.sitemap_cache.as_ref().map(|dir| {
+                SitemapCache::new(dir)
+                    .with_headers(&client::sitemap_headers(config))
+                    .include_authenticated(config.cache_authenticated_sitemaps)
+            }),
+        }))
+    }
+
+    /// load the page URLs from the given sitemap as a stream, so they can be
+    /// used while the rest of the sitemaps are still loading.
+    ///
+    /// Sub-sitemaps of sitemap indexes are fetched concurrently, limited by
+    /// `Config::concurrent_sitemap_requests`, while keeping the order of the
+    /// URLs. Transient failures are retried `Config::sitemap_retries` times.
+    /// With `Config::skip_broken_sitemaps`, child sitemaps that still fail are
+    /// logged and added to the skipped ones, otherwise the stream ends after
+    /// the first error.
+    ///
+    /// `file://` sitemaps are only read with `allow_local`, for locations given
+    /// by the user, and as children of local sitemaps. Remote sitemaps can't
+    /// make heater read local files.
+    pub fn stream(
+        &self,
+        url: Url,
+        allow_local: bool,
+    ) -> BoxStream<'static, Result<Entry, SiteMapError>> {
+        if url.scheme() == "file" && !allow_local {
+            return stream::once(future::ready(Err(SiteMapError::NotAllowed(url)))).boxed();
+        }
+
+        let context = self.0.clone();
+        stream::once(load(context.clone(), url, Vec::new()))
+            .flat_map(move |loaded| expand(context.clone(), loaded))
+            .scan(false, |failed, result| {
+                // stop after the first error
+                let item = if *failed { None } else { Some(result) };
+                *failed = matches!(item, Some(Err(_)));
+                future::ready(item)
+            })
+            .boxed()
+    }
+}
+
+/// parse a sitemap location given on the command line, which is either an URL
+/// or a path to a local file.
+pub fn parse_location(location: &str) -> Result<Url, SiteMapError> {
+    match Url::parse(location) {
+        // single-letter schemes are windows drive letters like `C:\`
+        Ok(url) if url.scheme().len() > 1 => Ok(url),
+        _ => {
+            let path = std::path::absolute(location).map_err(SiteMapError::File)?;
+            Url::from_file_path(&path).map_err(|_| SiteMapError::InvalidPath(location.to_owned()))
+        }
+    }
+}
+
+/// read a newline-separated list of URLs from stdin.
+pub fn from_stdin() -> Result<Vec<Entry>, SiteMapError> {
+    let mut body = Vec::new();
+    std::io::stdin()
+        .read_to_end(&mut body)
+        .map_err(SiteMapError::File)?;
+    Ok(parse_text(&body).into_iter().map(Entry::from).collect())
+}
+
+/// a loaded sitemap document, before parsing.
+struct Document {
+    url: Url,
+    content_type: Option<String>,
+    body: Vec<u8>,
+}
+
+/// state shared between all the sitemaps of a site.
+struct Context {
+    client: Client,
+    concurrency: usize,
+    visited: Mutex<HashSet<Url>>,
+    url_count: AtomicUsize,
+    max_depth: usize,
+    max_urls: usize,
+    max_size: usize,
+    parse_extensions: bool,
+    retries: usize,
+    skip_broken: bool,
+    skipped: SkippedSitemaps,
+    cache: Option<SitemapCache>,
+}
+
+/// a parsed sitemap, with the sub-sitemaps that still have to be loaded.
+#[derive(Default)]
+struct Loaded {
+    entries: Vec<Entry>,
+    sub_sitemaps: Vec<Url>,
+    parents: Vec<Url>,
+}
+
+/// the entries of a loaded sitemap, followed by the entries of its
+/// sub-sitemaps.
+///
+/// `buffered` fetches the sub-sitemaps concurrently while keeping their order.
+/// All sub-sitemaps of a sitemap are fetched before going deeper, so only one
+/// level is fetched at a time and no fetch is left waiting in an outer level.
+fn expand(
+    context: Arc<Context>,
+    loaded: Result<Loaded, SiteMapError>,
+) -> BoxStream<'static, Result<Entry, SiteMapError>> {
+    let Loaded {
+        entries,
+        sub_sitemaps,
+        parents,
+    } = match loaded {
+        Ok(loaded) => loaded,
+        Err(err) => return stream::once(future::ready(Err(err))).boxed(),
+    };
+
+    let sub_sitemaps = async move {
+        let loaded: Vec<_> = stream::iter(sub_sitemaps)
+            .map(|url| load(context.clone(), url, parents.clone()))
+            .buffered(context.concurrency)
+            .collect()
+            .await;
+        stream::iter(loaded).flat_map(move |loaded| expand(context.clone(), loaded))
+    };
+
+    stream::iter(entries.into_iter().map(Ok))
+        .chain(stream::once(sub_sitemaps).flatten())
+        .boxed()
+}
+
+/// load and parse a single sitemap, skipping broken child sitemaps when
+/// configured.
+///
+/// Hitting the URL limit is never skipped, since it applies to all sitemaps.
+async fn load(context: Arc<Context>, url: Url, parents: Vec<Url>) -> Result<Loaded, SiteMapError> {
+    let is_child = !parents.is_empty();
+
+    match load_one(&context, url.clone(), parents).await {
+        Err(err)
+            if context.skip_broken && is_child && !matches!(err, SiteMapError::TooManyUrls(_)) =>
+        {
+            log::error!("skipping broken sitemap {url}: {err}");
+            context.skipped.push(url, err);
+            Ok(Loaded::default())
+        }
+        result => result,
+    }
+}
+
+async fn load_one(context: &Context, url: Url, parents: Vec<Url>) -> Result<Loaded, SiteMapError> {
+    let allowed = match url.scheme() {
+        "http" | "https" => true,
+        // local roots are checked by `Loader::stream`
+        "file" => parents
+            .last()
+            .is_none_or(|parent| parent.scheme() == "file"),
+        _ => false,
+    };
+    if !allowed {
+        return Err(SiteMapError::NotAllowed(url));
+    }
+    if parents.contains(&url) {
+        return Err(SiteMapError::Cycle(url));
+    }
+    if parents.len() > context.max_depth {
+        return Err(SiteMapError::TooDeep {
+            url,
+            max_depth: context.max_depth,
+        });
+    }
+    if !context.visited.lock().unwrap().insert(url.clone()) {
+        log::warn!("skipping sitemap {url}, it was already loaded");
+        return Ok(Loaded::default());
+    }
+
+    let mut result: Vec<Entry> = Vec::new();
+    let mut sub_sitemaps: Vec<Url> = Vec::new();
+
+    let document = fetch_with_retries(context, url).await?;
+    let body = decompress(&document, context.max_size)?;
+
+    match detect_format(document.content_type.as_deref(), &body) {
+        Format::Xml => {
+            let parser = SiteMapReader::new(&body[..]);
+            for entity in parser {
+                match entity {
+                    SiteMapEntity::Url(url_entry) => match url_entry.loc.clone() {
+                        Location::None => {}
+                        Location::Url(url) => result.push(Entry::from_url_entry(url, url_entry)),
+                        Location::ParseErr(err) => {
+                            log::warn!("could not parse entry url: {err:?}")
+                        }
+                    },
+                    SiteMapEntity::SiteMap(sitemap_entry) => match sitemap_entry.loc {
+                        Location::None => {}
+                        Location::Url(url) => sub_sitemaps.push(url),
+                        Location::ParseErr(err) => {
+                            log::warn!("could not parse sitemap url: {err:?}")
+                        }
+                    },
+                    SiteMapEntity::Err(err) => return Err(SiteMapError::XmlError(err)),
                 }
-                Location::ParseErr(err) => log::warn!("could not parse sitemap url: {err:?}"),
-            },
-            SiteMapEntity::Err(err) => return Err(SiteMapError::XmlError(err)),
+            }
+
+            if context.parse_extensions && !result.is_empty() {
+                let mut extensions = extensions::parse(&body)?;
+                for entry in result.iter_mut() {
+                    if let Some(ext) = extensions.remove(&entry.url) {
+                        entry.alternates = ext.alternates;
+                        entry.media = ext.media;
+                    }
+                }
+            }
+        }
+        Format::Text => result = parse_text(&body).into_iter().map(Entry::from).collect(),
+        Format::Feed => {
+            result = feeds::parse(&document.url, &body)?
+                .into_iter()
+                .map(Entry::from)
+                .collect()
+        }
+    }
+
+    let total = context.url_count.fetch_add(result.len(), Ordering::Relaxed) + result.len();
+    if total > context.max_urls {
+        return Err(SiteMapError::TooManyUrls(context.max_urls));
+    }
+
+    for entry in result.iter_mut() {
+        entry.sitemap = Some(document.url.clone());
+    }
+
+    let mut parents = parents;
+    parents.push(document.url);
+
+    Ok(Loaded {
+        entries: result,
+        sub_sitemaps,
+        parents,
+    })
+}
+
+/// fetch a sitemap, retrying transient failures with an exponential backoff.
+async fn fetch_with_retries(context: &Context, url: Url) -> Result<Document, SiteMapError> {
+    let mut attempt: u32 = 0;
+    loop {
+        let result = fetch(
+            &context.client,
+            url.clone(),
+            context.max_size,
+            context.cache.as_ref(),
+        )
+        .await;
+
+        match result {
+            Err(err) if err.is_transient() && (attempt as usize) < context.retries => {
+                let delay = RETRY_DELAY * 2u32.pow(attempt);
+                attempt += 1;
+                log::warn!(
+                    "fetching sitemap {url} failed: {err}, retry {attempt}/{} in {delay:?}",
+                    context.retries
+                );
+                tokio::time::sleep(delay).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// load a sitemap via HTTP, or from the local filesystem for `file://` URLs.
+///
+/// Bodies larger than `max_size` and non-success responses are rejected.
+/// With a `cache`, cached sitemaps are revalidated with a conditional request
+/// and reused when they were not modified.
+async fn fetch(
+    client: &Client,
+    url: Url,
+    max_size: usize,
+    cache: Option<&SitemapCache>,
+) -> Result<Document, SiteMapError> {
+    let too_large = |url: Url| SiteMapError::TooLarge { url, max_size };
+
+    if url.scheme() == "file" {
+        let path = url
+            .to_file_path()
+            .map_err(|_| SiteMapError::InvalidPath(url.to_string()))?;
+
+        let mut body = Vec::new();
+        std::fs::File::open(path)
+            .and_then(|file| file.take(max_size as u64 + 1).read_to_end(&mut body))
+            .map_err(SiteMapError::File)?;
+        if body.len() > max_size {
+            return Err(too_large(url));
+        }
+
+        return Ok(Document {
+            url,
+            content_type: None,
+            body,
+        });
+    }
+
+    let cached = cache.and_then(|cache| cache.get(&url));
+    let mut request = client.get(url.clone());
+    if let Some(ref cached) = cached {
+        if let Some(ref etag) = cached.etag {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+        if let Some(ref last_modified) = cached.last_modified {
+            request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+
+    let requested_url = url;
+    let mut response = request.send().await?;
+    let url = response.url().clone();
+
+    if response.status() == StatusCode::NOT_MODIFIED
+        && let Some(cached) = cached
+    {
+        log::info!("sitemap {url} was not modified, using the cached copy");
+        if cached.body.len() > max_size {
+            return Err(too_large(url));
+        }
+        return Ok(Document {
+            url,
+            content_type: cached.content_type,
+            body: cached.body,
+        });
+    }
+    if !response.status().is_success() {
+        return Err(SiteMapError::HttpStatus {
+            url,
+            status: response.status(),
+        });
+    }
+    if response.content_length().unwrap_or(0) > max_size as u64 {
+        return Err(too_large(url));
+    }
+
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_lowercase());
+    let validator = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_owned)
+    };
+    let etag = validator(header::ETAG);
+    let last_modified = validator(header::LAST_MODIFIED);
+
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        body.extend_from_slice(&chunk);
+        if body.len() > max_size {
+            return Err(too_large(url));
         }
     }
 
-    Ok(result)
+    // without validators the cached copy could never be reused
+    if let Some(cache) = cache
+        && (etag.is_some() || last_modified.is_some())
+    {
+        let cached = Cached {
+            etag,
+            last_modified,
+            content_type: content_type.clone(),
+            body,
+        };
+        if let Err(err) = cache.put(&requested_url, &cached) {
+            log::warn!("could not cache sitemap {url}: {err}");
+        }
+        body = cached.body;
+    }
+
+    Ok(Document {
+        url,
+        content_type,
+        body,
+    })
+}
+
+/// detect the document format from the content-type, falling back to
+/// looking at the content itself when the content-type is not conclusive.
+fn detect_format(content_type: Option<&str>, body: &[u8]) -> Format {
+    match content_type {
+        Some(ct) if ct.starts_with("text/plain") => return Format::Text,
+        Some(ct) if ct.contains("rss") || ct.contains("atom") => return Format::Feed,
+        Some(ct) if ct.contains("xml") => return detect_xml_format(body),
+        _ => {}
+    }
+
+    match body.iter().find(|b| !b.is_ascii_whitespace()) {
+        Some(b'<') => detect_xml_format(body),
+        _ => Format::Text,
+    }
+}
+
+/// look at the root element to tell feeds from sitemaps.
+fn detect_xml_format(body: &[u8]) -> Format {
+    for event in EventReader::new(body) {
+        match event {
+            Ok(XmlEvent::StartElement { name, .. }) => {
+                let root = name.local_name.to_lowercase();
+                if feeds::FEED_ROOT_ELEMENTS.contains(&root.as_str()) {
+                    return Format::Feed;
+                } else {
+                    return Format::Xml;
+                }
+            }
+            Ok(_) => {}
+            // errors are reported by the sitemap parser
+            Err(_) => return Format::Xml,
+        }
+    }
+
+    Format::Xml
+}
+
+/// parse a plain text sitemap, one URL per line.
+fn parse_text(body: &[u8]) -> Vec<Url> {
+    String::from_utf8_lossy(body)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .filter_map(|line| match Url::parse(line) {
+            Ok(url) => Some(url),
+            Err(err) => {
+                log::warn!("could not parse entry url: {err:?}");
+                None
+            }
+        })
+        .collect()
+}
+
+/// decompress the document body when it is a gzipped sitemap.
+///
+/// `Content-Encoding: gzip` is already handled by reqwest, this is about
+/// sitemaps that are served as gzip files, like `sitemap.xml.gz`.
+fn decompress(document: &Document, max_size: usize) -> Result<Vec<u8>, SiteMapError> {
+    let looks_gzipped = document.url.path().ends_with(".gz")
+        || document
+            .content_type
+            .as_ref()
+            .map(|v| v.contains("gzip"))
+            .unwrap_or(false);
+
+    if document.body.starts_with(&GZIP_MAGIC_BYTES) {
+        let mut decompressed = Vec::new();
+        GzDecoder::new(&document.body[..])
+            .take(max_size as u64 + 1)
+            .read_to_end(&mut decompressed)
+            .map_err(SiteMapError::Decompression)?;
+
+        if decompressed.len() > max_size {
+            return Err(SiteMapError::TooLarge {
+                url: document.url.clone(),
+                max_size,
+            });
+        }
+        Ok(decompressed)
+    } else {
+        if looks_gzipped {
+            log::debug!("sitemap looks gzipped but is not, parsing it uncompressed");
+        }
+        Ok(document.body.clone())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::Config;
+    use flate2::{write::GzEncoder, Compression};
+    use futures::TryStreamExt;
+    use std::io::Write;
+    use test_case::test_case;
+
+    async fn get<T: AsRef<str>>(config: &Config, url: T) -> Result<Vec<Entry>, SiteMapError> {
+        Loader::new(config, &SkippedSitemaps::default())
+            .stream(Url::parse(url.as_ref())?, true)
+            .try_collect()
+            .await
+    }
+
+    impl PartialEq<Url> for Entry {
+        fn eq(&self, other: &Url) -> bool {
+            self.url == *other
+        }
+    }
+
+    fn gzip(input: &str) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(input.as_bytes()).unwrap();
+        encoder.finish().unwrap()
+    }
 
     #[tokio::test]
     async fn does_not_exist() {
-        assert!(get("http://does/not/exist").await.is_err());
+        assert!(get(&Config::new(), "http://does/not/exist").await.is_err());
     }
 
     #[tokio::test]
@@ -73,7 +717,11 @@ mod tests {
             .create_async()
             .await;
 
-        assert!(get(&format!("{}/sitemap.xml", server.url())).await.is_err());
+        assert!(
+            get(&Config::new(), &format!("{}/sitemap.xml", server.url()))
+                .await
+                .is_err()
+        );
     }
 
     #[tokio::test]
@@ -95,7 +743,9 @@ mod tests {
             .await;
 
         assert_eq!(
-            get(&format!("{}/sitemap.xml", server.url())).await.unwrap()[..],
+            get(&Config::new(), &format!("{}/sitemap.xml", server.url()))
+                .await
+                .unwrap()[..],
             [Url::parse("http://www.example.com/").unwrap()],
         );
     }
@@ -135,8 +785,884 @@ mod tests {
             .await;
 
         assert_eq!(
-            get(&format!("{}/sitemap.xml", server.url())).await.unwrap()[..],
+            get(&Config::new(), &format!("{}/sitemap.xml", server.url()))
+                .await
+                .unwrap()[..],
+            [Url::parse("http://www.example11.com/").unwrap()],
+        );
+    }
+
+    #[tokio::test]
+    async fn load_gzipped_sitemap() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/sitemap.xml.gz")
+            .with_status(200)
+            .with_header("content-type", "application/gzip")
+            .with_body(gzip(
+                r#"
+              <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+                <url>
+                    <loc>http://www.example.com/</loc>
+                </url>
+            </urlset>"#,
+            ))
+            .create_async()
+            .await;
+
+        assert_eq!(
+            get(&Config::new(), &format!("{}/sitemap.xml.gz", server.url()))
+                .await
+                .unwrap()[..],
+            [Url::parse("http://www.example.com/").unwrap()],
+        );
+    }
+
+    #[tokio::test]
+    async fn load_gzipped_sub_sitemaps() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/sitemap.xml")
+            .with_status(200)
+            .with_header("content-type", "text/xml")
+            .with_body(format!(
+                r#"
+                <sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+                    <sitemap>
+                        <loc>{}/real_sitemap.xml.gz</loc>
+                    </sitemap>
+                </sitemapindex>"#,
+                server.url()
+            ))
+            .create_async()
+            .await;
+
+        // no gzip content-type here, detection has to work via magic bytes.
+        let _i = server
+            .mock("GET", "/real_sitemap.xml.gz")
+            .with_status(200)
+            .with_header("content-type", "application/octet-stream")
+            .with_body(gzip(
+                r#"
+                <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+                    <url>
+                        <loc>http://www.example11.com/</loc>
+                    </url>
+                </urlset>"#,
+            ))
+            .create_async()
+            .await;
+
+        assert_eq!(
+            get(&Config::new(), &format!("{}/sitemap.xml", server.url()))
+                .await
+                .unwrap()[..],
             [Url::parse("http://www.example11.com/").unwrap()],
         );
     }
+
+    #[tokio::test]
+    async fn gzip_extension_but_plain_body() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/sitemap.xml.gz")
+            .with_status(200)
+            .with_header("content-type", "application/x-gzip")
+            .with_body(
+                r#"
+              <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+                <url>
+                    <loc>http://www.example.com/</loc>
+                </url>
+            </urlset>"#,
+            )
+            .create_async()
+            .await;
+
+        assert_eq!(
+            get(&Config::new(), &format!("{}/sitemap.xml.gz", server.url()))
+                .await
+                .unwrap()[..],
+            [Url::parse("http://www.example.com/").unwrap()],
+        );
+    }
+
+    #[tokio::test]
+    async fn truncated_gzip() {
+        let mut server = mockito::Server::new_async().await;
+        let body = gzip("<urlset></urlset>");
+        let _m = server
+            .mock("GET", "/sitemap.xml.gz")
+            .with_status(200)
+            .with_body(&body[..body.len() / 2])
+            .create_async()
+            .await;
+
+        assert!(matches!(
+            get(&Config::new(), &format!("{}/sitemap.xml.gz", server.url())).await,
+            Err(SiteMapError::Decompression(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn load_text_sitemap() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/sitemap.txt")
+            .with_status(200)
+            .with_header("content-type", "text/plain; charset=utf-8")
+            .with_body("http://www.example.com/\n\n  http://www.example.com/second  \nnot a url\n")
+            .create_async()
+            .await;
+
+        assert_eq!(
+            get(&Config::new(), &format!("{}/sitemap.txt", server.url()))
+                .await
+                .unwrap()[..],
+            [
+                Url::parse("http://www.example.com/").unwrap(),
+                Url::parse("http://www.example.com/second").unwrap(),
+            ],
+        );
+    }
+
+    #[tokio::test]
+    async fn load_feed_without_content_type() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/feed")
+            .with_status(200)
+            .with_body(
+                r#"<?xml version="1.0"?>
+                <rss version="2.0">
+                  <channel>
+                    <item><link>http://www.example.com/news/1</link></item>
+                  </channel>
+                </rss>"#,
+            )
+            .create_async()
+            .await;
+
+        assert_eq!(
+            get(&Config::new(), &format!("{}/feed", server.url()))
+                .await
+                .unwrap()[..],
+            [Url::parse("http://www.example.com/news/1").unwrap()],
+        );
+    }
+
+    #[test_case(Some("text/plain"), "<urlset />", Format::Text)]
+    #[test_case(Some("application/rss+xml"), "", Format::Feed)]
+    #[test_case(Some("application/atom+xml; charset=utf-8"), "", Format::Feed)]
+    #[test_case(Some("text/xml"), "<urlset />", Format::Xml)]
+    #[test_case(Some("text/xml"), "<feed />", Format::Feed)]
+    #[test_case(Some("application/xml"), "asdf", Format::Xml)]
+    #[test_case(
+        None,
+        "<?xml version=\"1.0\"?><rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\" />",
+        Format::Feed
+    )]
+    #[test_case(None, "<sitemapindex />", Format::Xml)]
+    #[test_case(
+        Some("application/octet-stream"),
+        "http://www.example.com/",
+        Format::Text
+    )]
+    fn format_detection(content_type: Option<&str>, body: &str, expected: Format) {
+        assert_eq!(detect_format(content_type, body.as_bytes()), expected);
+    }
+
+    #[test]
+    fn location_url() {
+        assert_eq!(
+            parse_location("https://www.example.com/sitemap.xml").unwrap(),
+            Url::parse("https://www.example.com/sitemap.xml").unwrap()
+        );
+    }
+
+    #[test]
+    fn location_path() {
+        let url = parse_location("sitemap.xml").unwrap();
+        assert_eq!(url.scheme(), "file");
+        assert_eq!(
+            url.to_file_path().unwrap(),
+            std::env::current_dir().unwrap().join("sitemap.xml")
+        );
+    }
+
+    #[tokio::test]
+    async fn load_local_sitemap_file() {
+        let dir = std::env::temp_dir().join(format!("heater-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let child = dir.join("child.xml.gz");
+        std::fs::write(
+            &child,
+            gzip(
+                r#"<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+                    <url><loc>http://www.example.com/</loc></url>
+                </urlset>"#,
+            ),
+        )
+        .unwrap();
+
+        let index = dir.join("sitemap.xml");
+        std::fs::write(
+            &index,
+            format!(
+                r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+                    <sitemap><loc>{}</loc></sitemap>
+                </sitemapindex>"#,
+                Url::from_file_path(&child).unwrap()
+            ),
+        )
+        .unwrap();
+
+        let result = get(
+            &Config::new(),
+            parse_location(index.to_str().unwrap()).unwrap(),
+        )
+        .await;
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            result.unwrap()[..],
+            [Url::parse("http://www.example.com/").unwrap()],
+        );
+    }
+
+    #[tokio::test]
+    async fn remote_index_with_local_child() {
+        let dir = std::env::temp_dir().join(format!("heater-test-remote-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let local = dir.join("secret.txt");
+        std::fs::write(&local, "http://www.example.com/secret\n").unwrap();
+        let local = Url::from_file_path(&local).unwrap();
+
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/sitemap.xml")
+            .with_status(200)
+            .with_header("content-type", "text/xml")
+            .with_body(format!(
+                r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+                    <sitemap><loc>{local}</loc></sitemap>
+                </sitemapindex>"#
+            ))
+            .create_async()
+            .await;
+        let index = format!("{}/sitemap.xml", server.url());
+
+        let result = get(&Config::new(), &index).await;
+        assert!(matches!(result, Err(SiteMapError::NotAllowed(ref url)) if *url == local));
+
+        let mut config = Config::new();
+        config.skip_broken_sitemaps = true;
+        let skipped = SkippedSitemaps::default();
+        let entries: Vec<Entry> = Loader::new(&config, &skipped)
+            .stream(Url::parse(&index).unwrap(), true)
+            .try_collect()
+            .await
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(entries.is_empty());
+        let skipped = skipped.take();
+        assert_eq!(skipped[0].url, local);
+        assert!(matches!(skipped[0].error, SiteMapError::NotAllowed(_)));
+    }
+
+    #[tokio::test]
+    async fn local_file_not_allowed() {
+        assert!(matches!(
+            Loader::new(&Config::new(), &SkippedSitemaps::default())
+                .stream(Url::parse("file:///etc/hosts").unwrap(), false)
+                .try_collect::<Vec<Entry>>()
+                .await,
+            Err(SiteMapError::NotAllowed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn missing_local_file() {
+        assert!(matches!(
+            get(&Config::new(), "file:///does/not/exist.xml").await,
+            Err(SiteMapError::File(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn sub_sitemaps_keep_order() {
+        let mut server = mockito::Server::new_async().await;
+
+        let children: String = (0..10)
+            .map(|i| {
+                format!(
+                    "<sitemap><loc>{}/sitemap_{i}.xml</loc></sitemap>",
+                    server.url()
+                )
+            })
+            .collect();
+        let _m = server
+            .mock("GET", "/sitemap.xml")
+            .with_status(200)
+            .with_header("content-type", "text/xml")
+            .with_body(format!(
+                r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">{children}</sitemapindex>"#
+            ))
+            .create_async()
+            .await;
+
+        let mut mocks = Vec::new();
+        for i in 0..10 {
+            mocks.push(
+                server
+                    .mock("GET", format!("/sitemap_{i}.xml").as_str())
+                    .with_status(200)
+                    .with_header("content-type", "text/xml")
+                    .with_body(format!(
+                        r#"<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+                            <url><loc>http://www.example.com/{i}/a</loc></url>
+                            <url><loc>http://www.example.com/{i}/b</loc></url>
+                        </urlset>"#
+                    ))
+                    .create_async()
+                    .await,
+            );
+        }
+
+        let mut config = Config::new();
+        config.concurrent_sitemap_requests = 3;
+
+        let expected: Vec<Url> = (0..10)
+            .flat_map(|i| {
+                ["a", "b"].map(|p| Url::parse(&format!("http://www.example.com/{i}/{p}")).unwrap())
+            })
+            .collect();
+
+        assert_eq!(
+            get(&config, format!("{}/sitemap.xml", server.url()))
+                .await
+                .unwrap(),
+            expected
+        );
+
+        for m in mocks {
+            m.assert_async().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn nested_indexes_with_slow_siblings() {
+        let mut server = mockito::Server::new_async().await;
+        let url = server.url();
+
+        let mut mocks = Vec::new();
+        for (path, children) in [
+            ("/root.xml", ["a.xml", "b.txt"]),
+            ("/a.xml", ["a1.xml", "a2.txt"]),
+        ] {
+            let children: String = children
+                .iter()
+                .map(|child| format!("<sitemap><loc>{url}/{child}</loc></sitemap>"))
+                .collect();
+            mocks.push(
+                server
+                    .mock("GET", path)
+                    .with_status(200)
+                    .with_header("content-type", "text/xml")
+                    .with_body(format!(
+                        r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">{children}</sitemapindex>"#
+                    ))
+                    .create_async()
+                    .await,
+            );
+        }
+        mocks.push(
+            server
+                .mock("GET", "/a1.xml")
+                .with_status(200)
+                .with_header("content-type", "text/xml")
+                .with_body(format!(
+                    r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+                        <sitemap><loc>{url}/a1.txt</loc></sitemap>
+                    </sitemapindex>"#
+                ))
+                .create_async()
+                .await,
+        );
+        for (name, delay) in [("a1", 0), ("a2", 200), ("b", 200)] {
+            mocks.push(
+                server
+                    .mock("GET", format!("/{name}.txt").as_str())
+                    .with_status(200)
+                    .with_header("content-type", "text/plain")
+                    .with_chunked_body(move |w| {
+                        std::thread::sleep(Duration::from_millis(delay));
+                        w.write_all(format!("http://www.example.com/{name}\n").as_bytes())
+                    })
+                    .create_async()
+                    .await,
+            );
+        }
+
+        // the slow siblings are still loading while the deeper levels are
+        let mut config = Config::new();
+        config.concurrent_sitemap_requests = 2;
+        let entries = tokio::time::timeout(
+            Duration::from_secs(10),
+            get(&config, format!("{url}/root.xml")),
+        )
+        .await
+        .expect("loading the sitemaps hangs")
+        .unwrap();
+
+        let expected = ["a1", "a2", "b"]
+            .map(|name| Url::parse(&format!("http://www.example.com/{name}")).unwrap());
+        assert_eq!(entries[..], expected);
+        for m in mocks {
+            m.assert_async().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn sitemap_cycle() {
+        let mut server = mockito::Server::new_async().await;
+        let m = server
+            .mock("GET", "/sitemap.xml")
+            .with_status(200)
+            .with_header("content-type", "text/xml")
+            .with_body(format!(
+                r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+                    <sitemap><loc>{}/sitemap.xml</loc></sitemap>
+                </sitemapindex>"#,
+                server.url()
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+
+        assert!(matches!(
+            get(&Config::new(), format!("{}/sitemap.xml", server.url())).await,
+            Err(SiteMapError::Cycle(_))
+        ));
+        m.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn sitemap_too_deep() {
+        let mut server = mockito::Server::new_async().await;
+        let mut mocks = Vec::new();
+        for i in 0..3 {
+            mocks.push(
+                server
+                    .mock("GET", format!("/sitemap_{i}.xml").as_str())
+                    .with_status(200)
+                    .with_header("content-type", "text/xml")
+                    .with_body(format!(
+                        r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+                            <sitemap><loc>{}/sitemap_{}.xml</loc></sitemap>
+                        </sitemapindex>"#,
+                        server.url(),
+                        i + 1
+                    ))
+                    .create_async()
+                    .await,
+            );
+        }
+
+        let mut config = Config::new();
+        config.max_sitemap_depth = 1;
+
+        assert!(matches!(
+            get(&config, format!("{}/sitemap_0.xml", server.url())).await,
+            Err(SiteMapError::TooDeep { max_depth: 1, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn too_many_urls() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/sitemap.txt")
+            .with_status(200)
+            .with_header("content-type", "text/plain")
+            .with_body(
+                "http://www.example.com/1\nhttp://www.example.com/2\nhttp://www.example.com/3",
+            )
+            .create_async()
+            .await;
+
+        let mut config = Config::new();
+        config.max_sitemap_urls = 2;
+
+        assert!(matches!(
+            get(&config, format!("{}/sitemap.txt", server.url())).await,
+            Err(SiteMapError::TooManyUrls(2))
+        ));
+    }
+
+    #[test_case(false; "plain")]
+    #[test_case(true; "gzipped")]
+    #[tokio::test]
+    async fn sitemap_too_large(gzipped: bool) {
+        let body = format!(
+            r#"<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">{}</urlset>"#,
+            "<url><loc>http://www.example.com/</loc></url>".repeat(100)
+        );
+
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/sitemap.xml")
+            .with_status(200)
+            .with_header("content-type", "text/xml")
+            .with_body(if gzipped {
+                gzip(&body)
+            } else {
+                body.into_bytes()
+            })
+            .create_async()
+            .await;
+
+        let mut config = Config::new();
+        config.max_sitemap_size = 1000;
+
+        assert!(matches!(
+            get(&config, format!("{}/sitemap.xml", server.url())).await,
+            Err(SiteMapError::TooLarge { max_size: 1000, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn load_entry_metadata() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/sitemap.xml")
+            .with_status(200)
+            .with_header("content-type", "text/xml")
+            .with_body(
+                r#"
+              <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+                <url>
+                    <loc>http://www.example.com/</loc>
+                    <lastmod>2021-10-01T12:00:00+02:00</lastmod>
+                    <changefreq>daily</changefreq>
+                    <priority>0.8</priority>
+                </url>
+                <url>
+                    <loc>http://www.example.com/other</loc>
+                    <changefreq>sometimes</changefreq>
+                </url>
+            </urlset>"#,
+            )
+            .create_async()
+            .await;
+
+        let sitemap = Url::parse(&format!("{}/sitemap.xml", server.url())).unwrap();
+        let entries = get(&Config::new(), &sitemap).await.unwrap();
+
+        assert_eq!(
+            entries[..],
+            [
+                Entry {
+                    url: Url::parse("http://www.example.com/").unwrap(),
+                    lastmod: Some(
+                        DateTime::parse_from_rfc3339("2021-10-01T12:00:00+02:00").unwrap()
+                    ),
+                    changefreq: Some(ChangeFreq::Daily),
+                    priority: Some(0.8),
+                    alternates: Vec::new(),
+                    media: Vec::new(),
+                    sitemap: Some(sitemap.clone()),
+                    warmed: None,
+                },
+                Entry {
+                    sitemap: Some(sitemap.clone()),
+                    warmed: None,
+                    ..Entry::from(Url::parse("http://www.example.com/other").unwrap())
+                },
+            ]
+        );
+    }
+
+    #[test_case(false; "disabled")]
+    #[test_case(true; "enabled")]
+    #[tokio::test]
+    async fn load_hreflang_alternates(enabled: bool) {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/sitemap.xml")
+            .with_status(200)
+            .with_header("content-type", "text/xml")
+            .with_body(
+                r#"
+              <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9"
+                      xmlns:xhtml="http://www.w3.org/1999/xhtml">
+                <url>
+                    <loc>http://www.example.com/</loc>
+                    <xhtml:link rel="alternate" hreflang="de" href="http://www.example.com/de/" />
+                </url>
+            </urlset>"#,
+            )
+            .create_async()
+            .await;
+
+        let mut config = Config::new();
+        config.hreflang = enabled;
+
+        let entries = get(&config, format!("{}/sitemap.xml", server.url()))
+            .await
+            .unwrap();
+
+        assert_eq!(entries.len(), 1);
+        if enabled {
+            assert_eq!(
+                entries[0].alternates[..],
+                [Alternate {
+                    hreflang: "de".into(),
+                    url: Url::parse("http://www.example.com/de/").unwrap(),
+                }]
+            );
+        } else {
+            assert!(entries[0].alternates.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn stream_yields_entries_until_error() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/sitemap.xml")
+            .with_status(200)
+            .with_header("content-type", "text/xml")
+            .with_body(format!(
+                r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+                    <sitemap><loc>{0}/good.xml</loc></sitemap>
+                    <sitemap><loc>{0}/broken.xml</loc></sitemap>
+                    <sitemap><loc>{0}/good2.xml</loc></sitemap>
+                </sitemapindex>"#,
+                server.url()
+            ))
+            .create_async()
+            .await;
+
+        let _g = server
+            .mock("GET", mockito::Matcher::Regex("^/good2?.xml$".into()))
+            .with_status(200)
+            .with_header("content-type", "text/plain")
+            .with_body("http://www.example.com/")
+            .create_async()
+            .await;
+
+        let _b = server
+            .mock("GET", "/broken.xml")
+            .with_status(200)
+            .with_header("content-type", "text/xml")
+            .with_body("<urlset>")
+            .create_async()
+            .await;
+
+        let results: Vec<_> = Loader::new(&Config::new(), &SkippedSitemaps::default())
+            .stream(
+                Url::parse(&format!("{}/sitemap.xml", server.url())).unwrap(),
+                false,
+            )
+            .collect()
+            .await;
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(
+            *results[0].as_ref().unwrap(),
+            Url::parse("http://www.example.com/").unwrap()
+        );
+        assert!(matches!(results[1], Err(SiteMapError::XmlError(_))));
+    }
+
+    #[tokio::test]
+    async fn http_status_error() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/sitemap.xml")
+            .with_status(404)
+            .with_header("content-type", "text/html")
+            .with_body("<html><body>not found</body></html>")
+            .create_async()
+            .await;
+
+        let mut config = Config::new();
+        config.sitemap_retries = 0;
+
+        assert!(matches!(
+            get(&config, &format!("{}/sitemap.xml", server.url())).await,
+            Err(SiteMapError::HttpStatus { status, .. }) if status == StatusCode::NOT_FOUND
+        ));
+    }
+
+    #[tokio::test]
+    async fn cache_conditional_requests() {
+        let mut server = mockito::Server::new_async().await;
+        let sitemap_url = format!("{}/sitemap.xml", server.url());
+        let last_modified = "Wed, 21 Oct 2015 07:28:00 GMT";
+
+        let full = server
+            .mock("GET", "/sitemap.xml")
+            .match_header("if-none-match", mockito::Matcher::Missing)
+            .with_status(200)
+            .with_header("content-type", "text/plain")
+            .with_header("etag", "\"v1\"")
+            .with_header("last-modified", last_modified)
+            .with_body("http://www.example.com/\n")
+            .expect(1)
+            .create_async()
+            .await;
+        let not_modified = server
+            .mock("GET", "/sitemap.xml")
+            .match_header("if-none-match", "\"v1\"")
+            .match_header("if-modified-since", last_modified)
+            .with_status(304)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let dir =
+            std::env::temp_dir().join(format!("heater-test-sitemap-cache-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut config = Config::new();
+        config.sitemap_cache = Some(dir);
+
+        let expected = [Url::parse("http://www.example.com/").unwrap()];
+        assert_eq!(get(&config, &sitemap_url).await.unwrap()[..], expected);
+        assert_eq!(get(&config, &sitemap_url).await.unwrap()[..], expected);
+
+        full.assert_async().await;
+        not_modified.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn cache_authenticated() {
+        let mut server = mockito::Server::new_async().await;
+        let sitemap_url = format!("{}/sitemap.xml", server.url());
+        let full = server
+            .mock("GET", "/sitemap.xml")
+            .match_header("if-none-match", mockito::Matcher::Missing)
+            .with_status(200)
+            .with_header("content-type", "text/plain")
+            .with_header("etag", "\"v1\"")
+            .with_body("http://www.example.com/\n")
+            .expect(2)
+            .create_async()
+            .await;
+
+        let dir = std::env::temp_dir().join(format!(
+            "heater-test-sitemap-cache-auth-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut config = Config::new();
+        config.sitemap_cache = Some(dir.clone());
+        config.request_headers.insert(
+            header::AUTHORIZATION,
+            crate::config::parse_basic_auth("user:secret").unwrap(),
+        );
+
+        // downloaded every time, and never stored
+        get(&config, &sitemap_url).await.unwrap();
+        get(&config, &sitemap_url).await.unwrap();
+
+        full.assert_async().await;
+        assert!(!dir.exists());
+    }
+
+    #[test_case(503, 1, true ; "transient status is retried")]
+    #[test_case(503, 0, false ; "no retries configured")]
+    #[test_case(404, 1, false ; "client errors are not retried")]
+    #[tokio::test]
+    async fn retry_transient_errors(status: usize, retries: usize, success: bool) {
+        let mut server = mockito::Server::new_async().await;
+        let _failure = server
+            .mock("GET", "/sitemap.xml")
+            .with_status(status)
+            .expect(1)
+            .create_async()
+            .await;
+        let _success = server
+            .mock("GET", "/sitemap.xml")
+            .with_status(200)
+            .with_header("content-type", "text/plain")
+            .with_body("http://www.example.com/")
+            .create_async()
+            .await;
+
+        let mut config = Config::new();
+        config.sitemap_retries = retries;
+
+        let result = get(&config, &format!("{}/sitemap.xml", server.url())).await;
+        assert_eq!(result.is_ok(), success);
+    }
+
+    #[tokio::test]
+    async fn skip_broken_sitemaps() {
+        let mut server = mockito::Server::new_async().await;
+        let _m = server
+            .mock("GET", "/sitemap.xml")
+            .with_status(200)
+            .with_header("content-type", "text/xml")
+            .with_body(format!(
+                r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+                    <sitemap><loc>{0}/missing.xml</loc></sitemap>
+                    <sitemap><loc>{0}/broken.xml</loc></sitemap>
+                    <sitemap><loc>{0}/good.xml</loc></sitemap>
+                </sitemapindex>"#,
+                server.url()
+            ))
+            .create_async()
+            .await;
+        let _missing = server
+            .mock("GET", "/missing.xml")
+            .with_status(404)
+            .create_async()
+            .await;
+        let _broken = server
+            .mock("GET", "/broken.xml")
+            .with_status(200)
+            .with_header("content-type", "text/xml")
+            .with_body("<urlset>")
+            .create_async()
+            .await;
+        let _good = server
+            .mock("GET", "/good.xml")
+            .with_status(200)
+            .with_header("content-type", "text/plain")
+            .with_body("http://www.example.com/")
+            .create_async()
+            .await;
+
+        let mut config = Config::new();
+        config.skip_broken_sitemaps = true;
+        let skipped = SkippedSitemaps::default();
+
+        let entries: Vec<Entry> = Loader::new(&config, &skipped)
+            .stream(
+                Url::parse(&format!("{}/sitemap.xml", server.url())).unwrap(),
+                false,
+            )
+            .try_collect()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            entries[..],
+            [Url::parse("http://www.example.com/").unwrap()]
+        );
+
+        // sub-sitemaps are loaded concurrently, so the order can vary
+        let mut skipped = skipped.take();
+        skipped.sort_by(|a, b| a.url.cmp(&b.url));
+        assert_eq!(
+            skipped.iter().map(|s| s.url.path()).collect::<Vec<_>>(),
+            ["/broken.xml", "/missing.xml"]
+        );
+        assert!(matches!(skipped[0].error, SiteMapError::XmlError(_)));
+        assert!(matches!(skipped[1].error, SiteMapError::HttpStatus { .. }));
+    }
 }
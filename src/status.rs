@@ -1,19 +1,56 @@
 use indicatif::{ProgressBar, ProgressStyle};
 use once_cell::sync::OnceCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 static PROGRESS: OnceCell<ProgressBar> = OnceCell::new();
 
-pub fn initialize_progress(len: u64) {
-    let bar = ProgressBar::new(len);
+/// the number of sites that are still discovering URLs.
+static DISCOVERING: AtomicUsize = AtomicUsize::new(0);
+
+fn style() -> ProgressStyle {
+    ProgressStyle::default_bar()
+        .template("[ETA: {eta_precise}] {wide_bar} {pos}/{len}")
+        .expect("could not initialize progress")
+}
+
+/// initialize a progress bar without known length, which grows via
+/// `add_length` while the URLs of the given number of sites are discovered.
+pub fn initialize_growing_progress(sites: usize) {
+    DISCOVERING.store(sites, Ordering::SeqCst);
+
+    let bar = ProgressBar::new(0);
     bar.set_style(
         ProgressStyle::default_bar()
-            .template("[ETA: {eta_precise}] {wide_bar} {pos}/{len}")
+            .template("[discovering] {wide_bar} {pos}/{len}+")
             .expect("could not initialize progress"),
     );
 
     let _ = PROGRESS.set(bar);
 }
 
+pub fn add_length(len: u64) {
+    if let Some(bar) = PROGRESS.get() {
+        bar.inc_length(len);
+    }
+}
+
+/// all URLs of a site are discovered, when this was the last site the length
+/// of the progress bar is final.
+pub fn finish_discovery() {
+    let remaining = DISCOVERING
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+            Some(n.saturating_sub(1))
+        })
+        .unwrap_or(0)
+        .saturating_sub(1);
+
+    if remaining == 0
+        && let Some(bar) = PROGRESS.get()
+    {
+        bar.set_style(style());
+    }
+}
+
 pub fn get_progress() -> Option<&'static ProgressBar> {
     PROGRESS.get()
 }
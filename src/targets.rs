@@ -0,0 +1,374 @@
+use crate::{config::Config, filters, heater::Warmed, rewrites, sitemaps::Entry};
+use reqwest::header::HeaderValue;
+use std::collections::HashSet;
+use url::Url;
+
+/// a single URL to warm, with everything that changes how it is warmed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub url: Url,
+    /// accept-language values to use instead of the configured languages
+    pub languages: Option<Vec<HeaderValue>>,
+    /// `Host` header to send instead of the one of the URL
+    pub host: Option<HeaderValue>,
+    /// the result of requesting the URL while crawling, used for its first
+    /// header variation
+    pub warmed: Option<Warmed>,
+}
+
+impl From<Url> for Target {
+    fn from(url: Url) -> Self {
+        Target {
+            url,
+            languages: None,
+            host: None,
+            warmed: None,
+        }
+    }
+}
+
+/// turns sitemap entries into targets one by one, so it can be used while
+/// the sitemaps are still loading.
+///
+/// With `Config::hreflang`, the hreflang alternates of every entry are warmed
+/// too, unless the URL patterns filter them out. With
+/// `Config::hreflang_languages`, every URL is warmed with the languages of its
+/// hreflang alternates instead of the configured languages.
+/// With `Config::include_media`, the images and videos of the entries that
+/// pass the URL patterns are collected in `media`. Only the added entries are
+/// looked at, so with `Config::sample` only the media of the sampled pages.
+/// All URLs are rewritten as configured, and only warmed once, in the order
+/// they are first seen. The crawler's result for a page is only used when the
+/// page isn't rewritten.
+#[derive(Debug, Default)]
+pub struct TargetBuilder {
+    seen: HashSet<Url>,
+    seen_media: HashSet<Url>,
+    /// the URLs rejected by the URL patterns.
+    filtered: HashSet<Url>,
+    pub media: Vec<Target>,
+}
+
+impl TargetBuilder {
+    /// whether the URL is already warmed as page or media.
+    pub fn contains(&self, url: &Url) -> bool {
+        self.seen.contains(url) || self.seen_media.contains(url)
+    }
+
+    /// how many distinct URLs were filtered out and not warmed anyway, from
+    /// the URLs of the `filtered_entries` and the alternates and media the
+    /// URL patterns rejected.
+    pub fn filtered_count(&self, filtered_entries: &HashSet<Url>) -> usize {
+        self.filtered
+            .union(filtered_entries)
+            .filter(|url| !self.contains(url))
+            .count()
+    }
+
+    /// returns the page targets for the entry, media targets are collected
+    /// in `self.media`.
+    pub fn add(&mut self, config: &Config, entry: Entry) -> Vec<Target> {
+        if config.include_media {
+            for url in &entry.media {
+                if !filters::is_url_match(config, url) {
+                    self.filtered.insert(url.clone());
+                    continue;
+                }
+                let (url, host) = rewrites::rewrite(config, url.clone());
+                if self.seen_media.insert(url.clone()) {
+                    self.media.push(Target {
+                        host,
+                        ..Target::from(url)
+                    });
+                }
+            }
+        }
+
+        let languages = if config.hreflang_languages {
+            hreflang_languages(&entry)
+        } else {
+            None
+        };
+
+        let alternates = if config.hreflang {
+            entry.alternates.into_iter().map(|a| a.url).collect()
+        } else {
+            Vec::new()
+        };
+
+        let mut warmed = entry.warmed.map(|warmed| (entry.url.clone(), warmed));
+        let mut result: Vec<Target> = Vec::new();
+        for url in std::iter::once(entry.url).chain(alternates) {
+            if !filters::is_url_match(config, &url) {
+                self.filtered.insert(url);
+                continue;
+            }
+            let (url, host) = rewrites::rewrite(config, url);
+            if self.seen.insert(url.clone()) {
+                // the crawler requested the entry URL with the default headers
+                let warmed = warmed
+                    .take()
+                    .filter(|(requested, _)| {
+                        *requested == url && host.is_none() && languages.is_none()
+                    })
+                    .map(|(_, warmed)| warmed);
+                result.push(Target {
+                    url,
+                    languages: languages.clone(),
+                    host,
+                    warmed,
+                });
+            }
+        }
+
+        result
+    }
+}
+
+/// the accept-language values of an entry, one per hreflang.
+///
+/// `x-default` is not a language, so it's skipped.
+fn hreflang_languages(entry: &Entry) -> Option<Vec<HeaderValue>> {
+    let mut languages: Vec<HeaderValue> = Vec::new();
+
+    for alternate in &entry.alternates {
+        if alternate.hreflang.eq_ignore_ascii_case("x-default") {
+            continue;
+        }
+        match HeaderValue::from_str(&alternate.hreflang) {
+            Ok(value) if !languages.contains(&value) => languages.push(value),
+            Ok(_) => {}
+            Err(err) => log::warn!("invalid hreflang {}: {err:?}", alternate.hreflang),
+        }
+    }
+
+    if languages.is_empty() {
+        None
+    } else {
+        Some(languages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sitemaps::Alternate;
+
+    /// returns the page and media targets for the entries
+    fn from_entries(config: &Config, entries: Vec<Entry>) -> (Vec<Target>, Vec<Target>) {
+        let mut builder = TargetBuilder::default();
+        let pages = entries
+            .into_iter()
+            .flat_map(|entry| builder.add(config, entry))
+            .collect();
+        (pages, builder.media)
+    }
+
+    fn url(path: &str) -> Url {
+        Url::parse("https://www.example.com/")
+            .unwrap()
+            .join(path)
+            .unwrap()
+    }
+
+    fn entries() -> Vec<Entry> {
+        let alternates = vec![
+            Alternate {
+                hreflang: "en".into(),
+                url: url("/en/"),
+            },
+            Alternate {
+                hreflang: "de".into(),
+                url: url("/de/"),
+            },
+            Alternate {
+                hreflang: "x-default".into(),
+                url: url("/"),
+            },
+        ];
+
+        vec![
+            Entry {
+                alternates: alternates.clone(),
+                ..Entry::from(url("/en/"))
+            },
+            Entry {
+                alternates,
+                ..Entry::from(url("/de/"))
+            },
+            Entry::from(url("/other")),
+        ]
+    }
+
+    #[test]
+    fn without_hreflang() {
+        let (pages, media) = from_entries(&Config::new(), entries());
+
+        assert!(media.is_empty());
+        assert_eq!(
+            pages[..],
+            [
+                Target::from(url("/en/")),
+                Target::from(url("/de/")),
+                Target::from(url("/other")),
+            ]
+        );
+    }
+
+    #[test]
+    fn hreflang_alternates() {
+        let mut config = Config::new();
+        config.hreflang = true;
+
+        let (pages, _) = from_entries(&config, entries());
+
+        assert_eq!(
+            pages[..],
+            [
+                Target::from(url("/en/")),
+                Target::from(url("/de/")),
+                Target::from(url("/")),
+                Target::from(url("/other")),
+            ]
+        );
+    }
+
+    #[test]
+    fn hreflang_alternates_filtered() {
+        let mut config = Config::new();
+        config.hreflang = true;
+        config.exclude = vec!["/de/**".parse().unwrap()];
+
+        let mut input = entries();
+        // the entry itself was already filtered
+        input.remove(1);
+        let mut builder = TargetBuilder::default();
+        let pages: Vec<Target> = input
+            .into_iter()
+            .flat_map(|entry| builder.add(&config, entry))
+            .collect();
+
+        assert_eq!(
+            pages[..],
+            [
+                Target::from(url("/en/")),
+                Target::from(url("/")),
+                Target::from(url("/other")),
+            ]
+        );
+        assert_eq!(builder.filtered_count(&HashSet::new()), 1);
+    }
+
+    #[test]
+    fn hreflang_languages() {
+        let mut config = Config::new();
+        config.hreflang = true;
+        config.hreflang_languages = true;
+
+        let (pages, _) = from_entries(&config, entries());
+        let languages = Some(vec![
+            HeaderValue::from_static("en"),
+            HeaderValue::from_static("de"),
+        ]);
+
+        assert_eq!(
+            pages[..],
+            [
+                Target {
+                    languages: languages.clone(),
+                    ..Target::from(url("/en/"))
+                },
+                Target {
+                    languages: languages.clone(),
+                    ..Target::from(url("/de/"))
+                },
+                Target {
+                    languages,
+                    ..Target::from(url("/"))
+                },
+                Target::from(url("/other")),
+            ]
+        );
+    }
+
+    #[test]
+    fn media() {
+        let mut input = entries();
+        input[0].media = vec![url("/image.jpg"), url("/video.mp4")];
+        input[2].media = vec![url("/image.jpg")];
+
+        let (_, media) = from_entries(&Config::new(), input.clone());
+        assert!(media.is_empty());
+
+        let mut config = Config::new();
+        config.include_media = true;
+        let (pages, media) = from_entries(&config, input);
+
+        assert_eq!(pages.len(), 3);
+        assert_eq!(
+            media[..],
+            [
+                Target::from(url("/image.jpg")),
+                Target::from(url("/video.mp4")),
+            ]
+        );
+    }
+
+    #[test]
+    fn media_filtered() {
+        let cdn = Url::parse("https://cdn.example.com/image.jpg").unwrap();
+        let mut input = entries();
+        input[0].media = vec![url("/image.jpg"), cdn.clone()];
+        input[2].media = vec![cdn];
+
+        let mut config = Config::new();
+        config.include_media = true;
+        config.exclude = vec!["host:cdn.example.com".parse().unwrap()];
+        let mut builder = TargetBuilder::default();
+        for entry in input {
+            builder.add(&config, entry);
+        }
+
+        assert_eq!(builder.media[..], [Target::from(url("/image.jpg"))]);
+        assert_eq!(builder.filtered_count(&HashSet::new()), 1);
+    }
+
+    #[test]
+    fn rewrites() {
+        let mut config = Config::new();
+        config.hreflang = true;
+        config.rewrite_host = Some("staging.example.com".parse().unwrap());
+        config.keep_host_header = true;
+
+        let (pages, _) = from_entries(&config, entries());
+        let staging = |path: &str| {
+            Url::parse("https://staging.example.com/")
+                .unwrap()
+                .join(path)
+                .unwrap()
+        };
+        let host = Some(HeaderValue::from_static("www.example.com"));
+
+        assert_eq!(
+            pages[..],
+            [
+                Target {
+                    host: host.clone(),
+                    ..Target::from(staging("/en/"))
+                },
+                Target {
+                    host: host.clone(),
+                    ..Target::from(staging("/de/"))
+                },
+                Target {
+                    host: host.clone(),
+                    ..Target::from(staging("/"))
+                },
+                Target {
+                    host,
+                    ..Target::from(staging("/other"))
+                },
+            ]
+        );
+    }
+}